@@ -0,0 +1,15 @@
+// Error definitions that may occur during operations on the liquidity pool.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    InsufficientLiquidity,
+    InvalidInput,
+    Overflow,
+    BelowMinimum,
+    StalePrice,
+    UnsupportedVersion,
+    Paused,
+    CapExceeded,
+    ExcessiveImpact,
+    InvariantViolation,
+    SimulationLimitExceeded,
+}
@@ -0,0 +1,16 @@
+// Widening casts of `Balance` to `u128` become no-ops under the `u128` feature.
+#![cfg_attr(feature = "u128", allow(clippy::unnecessary_cast))]
+
+mod error;
+mod pool;
+mod types;
+
+pub use error::Error;
+pub use pool::{
+    FeeCurve, FeeQuote, FeeSchedule, FeeStrategy, LinearFeeStrategy, LpPool, MetricsSink,
+    NormalizedState, Operation, OperationRecord, PoolConfig, PoolParamsRaw, PoolStats, PoolSummary,
+    QuadraticFeeStrategy, FIXED_BYTES_LEN,
+};
+pub use types::{
+    Address, Balance, LpTokenAmount, Percentage, Price, StakedTokenAmount, TokenAmount, SCALE,
+};
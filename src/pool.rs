@@ -0,0 +1,6021 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::types::{
+    Address, Balance, LpTokenAmount, Percentage, Price, StakedTokenAmount, TokenAmount, SCALE,
+    SECONDS_PER_YEAR,
+};
+
+// Structure representing the liquidity pool.
+#[derive(Debug, Clone)]
+pub struct LpPool {
+    price: Price,
+    token_amount: TokenAmount,
+    st_token_amount: StakedTokenAmount,
+    lp_token_amount: LpTokenAmount,
+    liquidity_target: TokenAmount,
+    // When set, overrides `liquidity_target` with this share of the pool's current total value
+    liquidity_target_fraction: Option<Percentage>,
+    min_fee: Percentage,
+    max_fee: Percentage,
+    applied_swap_keys: VecDeque<(u64, f64)>,
+    min_deposit: Option<TokenAmount>,
+    staked_reserve: StakedTokenAmount,
+    created_at: u64,
+    operation_count: u64,
+    price_updated_at: u64,
+    max_price_age: Option<u64>,
+    fee_waiver_until: Option<u64>,
+    lp_holdings: HashMap<Address, LpTokenAmount>,
+    metrics_sink: Option<Arc<dyn MetricsSink>>,
+    fee_rebates: HashMap<Address, Percentage>,
+    operation_log: Vec<OperationRecord>,
+    fee_strategy: Arc<dyn FeeStrategy>,
+    paused: bool,
+    max_token_amount: Option<TokenAmount>,
+    stats: PoolStats,
+    // Value gained by LPs in the most recent operation, in natural tokens
+    last_op_pnl: f64,
+    // Tokens paid for the staked balance, and its value at the prices it was acquired at
+    staked_cost_basis: TokenAmount,
+    staked_acquisition_value: TokenAmount,
+    // Tokens deposited by LPs still in the pool, reduced pro rata as LP tokens are burned
+    net_deposits: TokenAmount,
+    // Balances and LP supply as of the last operation, the backing `repair_lp_supply` restores
+    backing_reference: (TokenAmount, StakedTokenAmount, LpTokenAmount),
+}
+
+// Model deciding the swap fee from the token balance left in the pool after the swap.
+// Strategies are expected to charge a fee that does not rise as the balance grows.
+pub trait FeeStrategy: fmt::Debug + Send + Sync {
+    fn fee(
+        &self,
+        balance: TokenAmount,
+        target: TokenAmount,
+        min_fee: Percentage,
+        max_fee: Percentage,
+    ) -> Percentage;
+}
+
+// Default fee model: the minimum fee at or above the liquidity target, rising linearly
+// towards the maximum fee as the balance approaches zero.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinearFeeStrategy;
+
+impl FeeStrategy for LinearFeeStrategy {
+    fn fee(
+        &self,
+        balance: TokenAmount,
+        target: TokenAmount,
+        min_fee: Percentage,
+        max_fee: Percentage,
+    ) -> Percentage {
+        if balance.0 >= target.0 {
+            return min_fee;
+        }
+
+        let fee_spread = max_fee.0.saturating_sub(min_fee.0) as u128;
+        // The balance is below the target, so the discount never exceeds the spread
+        let discount =
+            mul_div(fee_spread, balance.0 as u128, target.0 as u128).unwrap_or(fee_spread);
+
+        Percentage(max_fee.0 - discount as u64)
+    }
+}
+
+// Fee model charging the maximum fee at an empty pool and the minimum fee at or above the
+// liquidity target, falling with the square of the balance's share of the target in between.
+// Below the target it charges at least as much as `LinearFeeStrategy`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuadraticFeeStrategy;
+
+impl FeeStrategy for QuadraticFeeStrategy {
+    fn fee(
+        &self,
+        balance: TokenAmount,
+        target: TokenAmount,
+        min_fee: Percentage,
+        max_fee: Percentage,
+    ) -> Percentage {
+        if balance.0 >= target.0 {
+            return min_fee;
+        }
+
+        let fee_spread = max_fee.0.saturating_sub(min_fee.0) as u128;
+        // The balance is below the target, so the discount never exceeds the spread
+        let discount = mul_div(fee_spread, balance.0 as u128, target.0 as u128)
+            .and_then(|discount| mul_div(discount, balance.0 as u128, target.0 as u128))
+            .unwrap_or(fee_spread);
+
+        Percentage(max_fee.0 - discount as u64)
+    }
+}
+
+// Built-in fee curve shapes, each backed by a `FeeStrategy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeCurve {
+    Linear,
+    Quadratic,
+}
+
+impl FeeCurve {
+    pub const ALL: [FeeCurve; 2] = [FeeCurve::Linear, FeeCurve::Quadratic];
+
+    pub fn strategy(self) -> Arc<dyn FeeStrategy> {
+        match self {
+            FeeCurve::Linear => Arc::new(LinearFeeStrategy),
+            FeeCurve::Quadratic => Arc::new(QuadraticFeeStrategy),
+        }
+    }
+}
+
+// State-changing operations that can be applied to the pool.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    AddLiquidity(TokenAmount),
+    RemoveLiquidity(LpTokenAmount),
+    Swap(StakedTokenAmount),
+}
+
+// An operation applied to the pool at `timestamp` (seconds), by `actor` when it was applied
+// on behalf of an address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OperationRecord {
+    pub timestamp: u64,
+    pub operation: Operation,
+    pub actor: Option<Address>,
+}
+
+// Receiver for per-operation metrics, e.g. an adapter incrementing Prometheus counters.
+// Every method defaults to a no-op so sinks only implement the events they care about.
+pub trait MetricsSink: fmt::Debug + Send + Sync {
+    fn record_add_liquidity(&self, _token_amount: TokenAmount, _lp_tokens_minted: LpTokenAmount) {}
+    fn record_remove_liquidity(&self, _lp_token_amount: LpTokenAmount) {}
+    fn record_swap(&self, _volume: StakedTokenAmount, _fee: Percentage) {}
+}
+
+// Result of pricing a swap against the current pool state.
+struct SwapQuote {
+    tokens_to_receive_f64: f64,
+    tokens_to_receive_scaled: Balance,
+    fee: Percentage,
+}
+
+// Fee for a given swap size, as a rate and as the tokens it withholds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeQuote {
+    pub rate: Percentage,
+    pub amount: TokenAmount,
+}
+
+// Breakdown of every fee rate configured on the pool.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeSchedule {
+    pub swap_min_fee: Percentage,
+    pub swap_max_fee: Percentage,
+}
+
+// Cumulative swap statistics collected since the pool was created or `reset_stats` was called.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolStats {
+    pub swap_count: u64,
+    pub staked_volume: StakedTokenAmount,
+    pub tokens_paid: TokenAmount,
+    pub fees_collected: TokenAmount,
+}
+
+impl PoolStats {
+    fn empty() -> Self {
+        PoolStats {
+            swap_count: 0,
+            staked_volume: StakedTokenAmount(0),
+            tokens_paid: TokenAmount(0),
+            fees_collected: TokenAmount(0),
+        }
+    }
+}
+
+// Snapshot of the pool for reporting, with amounts scaled down to "natural" f64 values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolSummary {
+    pub token_amount: f64,
+    pub st_token_amount: f64,
+    pub lp_token_amount: f64,
+    pub price: f64,
+    pub min_fee: Percentage,
+    pub max_fee: Percentage,
+    pub liquidity_target: f64,
+    pub reserve_ratio: f64,
+    pub lp_token_price: f64,
+}
+
+// Balances and price of the pool as plain "natural" f64 values, independent of `SCALE`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NormalizedState {
+    pub token_amount: f64,
+    pub st_token_amount: f64,
+    pub lp_token_amount: f64,
+    pub staked_reserve: f64,
+    pub liquidity_target: f64,
+    pub price: f64,
+}
+
+// Pool parameters as raw integers, e.g. read from a config file: scaled price and liquidity
+// target, and fees in basis points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolParamsRaw {
+    pub price: u64,
+    pub min_fee_bps: u64,
+    pub max_fee_bps: u64,
+    pub liquidity_target: Balance,
+}
+
+// The pool's configured parameters, as passed to `LpPool::init`, separate from its balances.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolConfig {
+    pub price: Price,
+    pub min_fee: Percentage,
+    pub max_fee: Percentage,
+    pub liquidity_target: TokenAmount,
+}
+
+// Most swaps `swaps_until_exhausted` and `simulate_shock` simulate before giving up.
+const MAX_SIMULATED_SWAPS: u64 = 100_000;
+
+// Number of most recent idempotency keys remembered by `swap_idempotent`.
+const IDEMPOTENCY_KEY_CAPACITY: usize = 64;
+
+// Version written as the first byte of `LpPool::to_bytes`; bumped whenever the layout changes.
+// `LpPool::from_bytes` still reads every earlier layout. Fields added by each version:
+//   1: balances, fees, min deposit, staked reserve, timestamps, operation count, max price age
+//   2: LP holdings, 3: fee rebates, 4: paused flag, 5: token cap, 6: staked cost basis and
+//   acquisition value, 7: net deposits, 8: liquidity target fraction, 9: fee waiver,
+//   10: backing reference
+const SERIALIZATION_VERSION: u8 = 10;
+
+// Length of `LpPool::to_fixed_bytes`: seven big-endian u64 fields.
+pub const FIXED_BYTES_LEN: usize = 7 * 8;
+
+// Rounding policy for swap payouts: round to the nearest scaled unit, with exact halves going
+// down in the pool's favor. Every other payout and mint truncates, which also favors the pool.
+fn round_payout(scaled: f64) -> Balance {
+    (scaled - 0.5).ceil().max(0.0) as Balance
+}
+
+// Integer counterpart of `round_payout` for `amount * price / SCALE`, saturating at `Balance::MAX`.
+fn round_payout_scaled(amount: u128, price: u128) -> Balance {
+    amount.checked_mul(price).map_or(Balance::MAX, |numerator| {
+        saturate_balance(numerator.saturating_add(SCALE as u128 / 2 - 1) / SCALE as u128)
+    })
+}
+
+// `a * b / divisor`, rounded down, through a 256-bit intermediate so that products of balances
+// anywhere in the `u128` backend's range cannot overflow. `None` if the divisor is zero or the
+// quotient does not fit in a u128.
+fn mul_div(a: u128, b: u128, divisor: u128) -> Option<u128> {
+    mul_div_rem(a, b, divisor).map(|(quotient, _)| quotient)
+}
+
+// `mul_div` rounded up.
+fn mul_div_ceil(a: u128, b: u128, divisor: u128) -> Option<u128> {
+    let (quotient, remainder) = mul_div_rem(a, b, divisor)?;
+    quotient.checked_add((remainder > 0) as u128)
+}
+
+// Quotient and remainder of `a * b / divisor` for `mul_div`.
+fn mul_div_rem(a: u128, b: u128, divisor: u128) -> Option<(u128, u128)> {
+    if divisor == 0 {
+        return None;
+    }
+    if let Some(product) = a.checked_mul(b) {
+        return Some((product / divisor, product % divisor));
+    }
+
+    // Schoolbook multiplication of the 64-bit halves into a `(high, low)` 256-bit product
+    const LOW_HALF: u128 = u64::MAX as u128;
+    let (a_high, a_low) = (a >> 64, a & LOW_HALF);
+    let (b_high, b_low) = (b >> 64, b & LOW_HALF);
+    let low_low = a_low * b_low;
+    let high_low = a_high * b_low;
+    let low_high = a_low * b_high;
+    let middle = (low_low >> 64) + (high_low & LOW_HALF) + (low_high & LOW_HALF);
+    let low = (middle << 64) | (low_low & LOW_HALF);
+    let high = a_high * b_high + (high_low >> 64) + (low_high >> 64) + (middle >> 64);
+    if high >= divisor {
+        return None;
+    }
+
+    // Long division one bit at a time; the remainder stays below the divisor, so the bit
+    // shifted out of it on overflow is accounted for by subtracting the divisor once
+    let (mut quotient, mut remainder) = (0u128, high);
+    for bit in (0..128).rev() {
+        let overflowed = remainder >> 127 == 1;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+        if overflowed || remainder >= divisor {
+            remainder = remainder.wrapping_sub(divisor);
+            quotient |= 1;
+        }
+    }
+    Some((quotient, remainder))
+}
+
+// Narrow a u128 intermediate to a `Balance`, saturating instead of wrapping.
+fn saturate_balance(value: u128) -> Balance {
+    Balance::try_from(value).unwrap_or(Balance::MAX)
+}
+
+// Cursor over a serialized pool, failing with `Error::InvalidInput` when the input runs out.
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl ByteReader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        if self.bytes.len() < N {
+            return Err(Error::InvalidInput);
+        }
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(head.try_into().expect("split_at returns exactly N bytes"))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn read_balance(&mut self) -> Result<Balance, Error> {
+        let value = self.take().map(u128::from_le_bytes)?;
+        Balance::try_from(value).map_err(|_| Error::Overflow)
+    }
+
+    fn read_flag(&mut self) -> Result<bool, Error> {
+        match self.take::<1>()? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(Error::InvalidInput),
+        }
+    }
+}
+
+// Methods implementation
+impl LpPool {
+    // Initialize the liquidity pool with basic parameters.
+    pub fn init(
+        price: Price,
+        min_fee: Percentage,
+        max_fee: Percentage,
+        liquidity_target: TokenAmount,
+    ) -> Result<Self, Error> {
+        Self::init_at(price, min_fee, max_fee, liquidity_target, 0)
+    }
+
+    // Initialize the liquidity pool from raw integer parameters, without any f64 intermediary.
+    pub fn from_raw_params(params: PoolParamsRaw) -> Result<Self, Error> {
+        if params.price == 0 {
+            return Err(Error::InvalidInput);
+        }
+
+        // One basis point is 1/10000, so it maps exactly onto `SCALE`
+        let bps_to_percentage = |bps: u64| {
+            bps.checked_mul(SCALE / 10_000)
+                .map(Percentage)
+                .ok_or(Error::InvalidInput)
+        };
+        Self::init(
+            Price(params.price),
+            bps_to_percentage(params.min_fee_bps)?,
+            bps_to_percentage(params.max_fee_bps)?,
+            TokenAmount(params.liquidity_target),
+        )
+    }
+
+    // Initialize the liquidity pool, recording `created_at` (seconds) as its creation time.
+    pub fn init_at(
+        price: Price,
+        min_fee: Percentage,
+        max_fee: Percentage,
+        liquidity_target: TokenAmount,
+        created_at: u64,
+    ) -> Result<Self, Error> {
+        // A fee above 100% would make swap outputs negative
+        if min_fee.0 > SCALE || max_fee.0 > SCALE {
+            return Err(Error::InvalidInput);
+        }
+
+        Ok(LpPool {
+            price,
+            token_amount: TokenAmount(0),
+            st_token_amount: StakedTokenAmount(0),
+            lp_token_amount: LpTokenAmount(0),
+            liquidity_target,
+            liquidity_target_fraction: None,
+            min_fee,
+            max_fee,
+            applied_swap_keys: VecDeque::with_capacity(IDEMPOTENCY_KEY_CAPACITY),
+            min_deposit: None,
+            staked_reserve: StakedTokenAmount(0),
+            created_at,
+            operation_count: 0,
+            price_updated_at: created_at,
+            max_price_age: None,
+            fee_waiver_until: None,
+            lp_holdings: HashMap::new(),
+            metrics_sink: None,
+            fee_rebates: HashMap::new(),
+            operation_log: Vec::new(),
+            fee_strategy: Arc::new(LinearFeeStrategy),
+            paused: false,
+            max_token_amount: None,
+            stats: PoolStats::empty(),
+            last_op_pnl: 0.0,
+            staked_cost_basis: TokenAmount(0),
+            staked_acquisition_value: TokenAmount(0),
+            net_deposits: TokenAmount(0),
+            backing_reference: (TokenAmount(0), StakedTokenAmount(0), LpTokenAmount(0)),
+        })
+    }
+
+    // Configure the smallest deposit accepted by `add_liquidity`, or `None` to accept any amount.
+    pub fn set_min_deposit(&mut self, min_deposit: Option<TokenAmount>) {
+        self.min_deposit = min_deposit;
+    }
+
+    // Configure the largest token balance `add_liquidity` may grow the pool to, or `None` for no cap.
+    pub fn set_max_token_amount(&mut self, max_token_amount: Option<TokenAmount>) {
+        self.max_token_amount = max_token_amount;
+    }
+
+    // Configure the liquidity target as a share of the pool's total value, recomputed as the pool
+    // grows or the price moves, or `None` to use the fixed token amount passed to `init`.
+    pub fn set_liquidity_target_fraction(
+        &mut self,
+        fraction: Option<Percentage>,
+    ) -> Result<(), Error> {
+        if fraction.is_some_and(|fraction| fraction.0 > SCALE) {
+            return Err(Error::InvalidInput);
+        }
+
+        self.liquidity_target_fraction = fraction;
+        Ok(())
+    }
+
+    // Liquidity target the fee curve is currently evaluated against: the configured share of
+    // the pool's total value if one is set, and the fixed token amount otherwise.
+    pub fn effective_liquidity_target(&self) -> TokenAmount {
+        let Some(fraction) = self.liquidity_target_fraction else {
+            return self.liquidity_target;
+        };
+
+        let pool_value = (self.token_amount.0 as u128)
+            .saturating_add(self.value_in_tokens(self.st_token_amount).0 as u128);
+        TokenAmount(
+            mul_div(pool_value, fraction.0 as u128, SCALE as u128)
+                .map_or(Balance::MAX, saturate_balance),
+        )
+    }
+
+    // Configure the staked token balance that is held back and never paid out.
+    pub fn set_staked_reserve(&mut self, staked_reserve: StakedTokenAmount) {
+        self.staked_reserve = staked_reserve;
+    }
+
+    // Configure the maximum price age (seconds) accepted by `swap_at`, or `None` to never reject stale prices.
+    // Only the timestamped swaps, `swap_at` and `swap_for_at`, enforce it: `swap`, `swap_for`,
+    // `swap_clamped`, `swap_max_impact` and `swap_idempotent` take no time and never check the
+    // price age, so integrations relying on this guard must route every swap through `swap_at`.
+    pub fn set_max_price_age(&mut self, max_price_age: Option<u64>) {
+        self.max_price_age = max_price_age;
+    }
+
+    // Waive swap fees in `swap_at` and `swap_for_at` before the `fee_waiver_until` timestamp
+    // (seconds), or `None` to always charge them.
+    pub fn set_fee_waiver_until(&mut self, fee_waiver_until: Option<u64>) {
+        self.fee_waiver_until = fee_waiver_until;
+    }
+
+    // Update the staked token price, recording `now` (seconds) as the time of the update.
+    pub fn update_price(&mut self, price: Price, now: u64) -> Result<(), Error> {
+        if price.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+
+        self.price = price;
+        self.price_updated_at = now;
+        Ok(())
+    }
+
+    // Check whether the price was last updated more than `max_age` seconds before `now`.
+    pub fn is_price_stale(&self, now: u64, max_age: u64) -> bool {
+        now.saturating_sub(self.price_updated_at) > max_age
+    }
+
+    // Attach a sink notified after every successful operation, or `None` to stop emitting metrics.
+    pub fn set_metrics_sink(&mut self, metrics_sink: Option<Arc<dyn MetricsSink>>) {
+        self.metrics_sink = metrics_sink;
+    }
+
+    // Copy of the pool for simulations; it does not report metrics for hypothetical operations
+    // and accepts them even while the live pool is paused.
+    fn hypothetical(&self) -> LpPool {
+        LpPool {
+            metrics_sink: None,
+            paused: false,
+            ..self.clone()
+        }
+    }
+
+    // Configure the share of the swap fee refunded to `address` in `swap_for`, or `None` to remove it.
+    pub fn set_fee_rebate(
+        &mut self,
+        address: Address,
+        rebate: Option<Percentage>,
+    ) -> Result<(), Error> {
+        match rebate {
+            Some(rebate) if rebate.0 > SCALE => return Err(Error::InvalidInput),
+            Some(rebate) => self.fee_rebates.insert(address, rebate),
+            None => self.fee_rebates.remove(&address),
+        };
+        Ok(())
+    }
+
+    // Replace the model used to compute swap fees.
+    pub fn set_fee_strategy(&mut self, fee_strategy: Arc<dyn FeeStrategy>) {
+        self.fee_strategy = fee_strategy;
+    }
+
+    // Pause the pool: state-changing operations fail with `Error::Paused` until `resume` is called.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    // Resume a paused pool.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    // Whether the pool is currently paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Reject state-changing operations while the pool is paused.
+    fn ensure_not_paused(&self) -> Result<(), Error> {
+        if self.paused {
+            return Err(Error::Paused);
+        }
+        Ok(())
+    }
+
+    // Add liquidity to the pool.
+    pub fn add_liquidity(&mut self, token_amount: TokenAmount) -> Result<f64, Error> {
+        self.ensure_not_paused()?;
+        if token_amount.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+        if let Some(min_deposit) = self.min_deposit {
+            if token_amount.0 < min_deposit.0 {
+                return Err(Error::BelowMinimum);
+            }
+        }
+        if let Some(max_token_amount) = self.max_token_amount {
+            if token_amount.0 > max_token_amount.0.saturating_sub(self.token_amount.0) {
+                return Err(Error::CapExceeded);
+            }
+        }
+
+        // LP tokens track the pool's total value (tokens plus staked tokens at the current price),
+        // not just its token balance, so a deposit never dilutes the value held by existing LPs.
+        let pool_value_scaled = self.total_value() * SCALE as f64;
+        let lp_tokens_to_mint_f64 = if self.lp_token_amount.0 > 0 && pool_value_scaled > 0.0 {
+            // Calculate proportional LP token minting based on existing ones
+            (token_amount.0 as f64 * self.lp_token_amount.0 as f64 / pool_value_scaled)
+                / SCALE as f64
+        } else {
+            // If the pool is empty, mint LP tokens 1:1 with tokens, anchoring one LP token to one token of value
+            token_amount.0 as f64 / SCALE as f64
+        };
+
+        let lp_tokens_minted = (lp_tokens_to_mint_f64 * SCALE as f64) as Balance; // Scale up to integer to update state
+        let new_token_amount = self
+            .token_amount
+            .0
+            .checked_add(token_amount.0)
+            .ok_or(Error::Overflow)?;
+        let new_lp_token_amount = self
+            .lp_token_amount
+            .0
+            .checked_add(lp_tokens_minted)
+            .ok_or(Error::Overflow)?;
+        self.token_amount.0 = new_token_amount;
+        self.lp_token_amount.0 = new_lp_token_amount;
+        self.net_deposits.0 = self.net_deposits.0.saturating_add(token_amount.0);
+        self.record_backing_reference();
+        self.operation_count += 1;
+        // The deposit adds exactly its own value, which belongs to the depositor
+        self.last_op_pnl = 0.0;
+
+        if let Some(metrics_sink) = &self.metrics_sink {
+            metrics_sink.record_add_liquidity(token_amount, LpTokenAmount(lp_tokens_minted));
+        }
+
+        Ok(lp_tokens_to_mint_f64)
+    }
+
+    // Add each deposit in order, returning the LP tokens minted for each. Either every deposit
+    // is applied or, if any fails, none are.
+    pub fn add_liquidity_batch(&mut self, deposits: &[TokenAmount]) -> Result<Vec<f64>, Error> {
+        self.ensure_not_paused()?;
+        let mut simulated_pool = self.hypothetical();
+        for deposit in deposits {
+            simulated_pool.add_liquidity(*deposit)?;
+        }
+
+        // The dry run succeeded, so replaying the deposits cannot fail
+        deposits
+            .iter()
+            .map(|deposit| self.add_liquidity(*deposit))
+            .collect()
+    }
+
+    // Remove liquidity from the pool. Burns only untracked LP tokens, those minted through
+    // `add_liquidity`; tokens credited to a depositor leave through `remove_liquidity_for`.
+    pub fn remove_liquidity(
+        &mut self,
+        lp_token_amount: LpTokenAmount,
+    ) -> Result<(f64, f64), Error> {
+        self.ensure_not_paused()?;
+        if lp_token_amount.0 > self.untracked_lp_supply().0 {
+            return Err(Error::InsufficientLiquidity);
+        }
+        self.burn_lp(lp_token_amount)
+    }
+
+    // Burn LP tokens from the whole supply, tracked or not, paying out their share of both sides.
+    fn burn_lp(&mut self, lp_token_amount: LpTokenAmount) -> Result<(f64, f64), Error> {
+        self.ensure_not_paused()?;
+        if lp_token_amount.0 == 0 || lp_token_amount.0 > self.lp_token_amount.0 {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        // Compute the payout in scaled integers, rounding down so the pool never pays out more than
+        // the burned LP tokens are worth. The remainder stays with the remaining LPs, and the last
+        // LP to exit receives everything that is left.
+        let share_of = |amount: Balance| {
+            mul_div(
+                amount as u128,
+                lp_token_amount.0 as u128,
+                self.lp_token_amount.0 as u128,
+            )
+            .map(saturate_balance)
+            .ok_or(Error::Overflow)
+        };
+        let token_amount_to_return = share_of(self.token_amount.0)?;
+        let staked_token_amount_to_return = share_of(self.st_token_amount.0)?;
+        let net_deposits_to_release = share_of(self.net_deposits.0)?;
+        self.take_cost_basis(StakedTokenAmount(staked_token_amount_to_return));
+        self.net_deposits.0 -= net_deposits_to_release;
+
+        // Remaining LPs keep whatever the rounded-down payout leaves behind
+        let value_before = self.total_liabilities().0 as f64;
+        let value_paid = token_amount_to_return as f64
+            + self
+                .value_in_tokens(StakedTokenAmount(staked_token_amount_to_return))
+                .0 as f64;
+        self.token_amount.0 -= token_amount_to_return;
+        self.st_token_amount.0 -= staked_token_amount_to_return;
+        self.lp_token_amount.0 -= lp_token_amount.0;
+        self.record_backing_reference();
+        self.operation_count += 1;
+        self.last_op_pnl =
+            (value_before - value_paid - self.total_liabilities().0 as f64) / SCALE as f64;
+
+        if let Some(metrics_sink) = &self.metrics_sink {
+            metrics_sink.record_remove_liquidity(lp_token_amount);
+        }
+
+        // Scale down the result to return the "natural" values
+        Ok((
+            token_amount_to_return as f64 / SCALE as f64,
+            staked_token_amount_to_return as f64 / SCALE as f64,
+        ))
+    }
+
+    // Add staking rewards earned on the staked balance without minting LP tokens, so the gain
+    // goes to existing LPs pro rata. Rewards cost the pool nothing and leave the cost basis as is.
+    pub fn accrue_rewards(&mut self, reward: StakedTokenAmount) -> Result<(), Error> {
+        self.ensure_not_paused()?;
+        // With no LP tokens outstanding the reward would go to whoever deposits first
+        if reward.0 == 0 || self.lp_token_amount.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+
+        self.st_token_amount.0 = self
+            .st_token_amount
+            .0
+            .checked_add(reward.0)
+            .ok_or(Error::Overflow)?;
+        self.record_backing_reference();
+        Ok(())
+    }
+
+    // Fewest LP tokens whose `remove_liquidity` pays out at least `tokens` on the token side.
+    pub fn lp_for_token_out(&self, tokens: TokenAmount) -> Result<LpTokenAmount, Error> {
+        if tokens.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+        if tokens.0 > self.token_amount.0 {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        // `remove_liquidity` rounds the payout down, so round the burn up
+        let lp_to_burn = mul_div_ceil(
+            tokens.0 as u128,
+            self.lp_token_amount.0 as u128,
+            self.token_amount.0 as u128,
+        )
+        .ok_or(Error::Overflow)?;
+        Ok(LpTokenAmount(saturate_balance(lp_to_burn)))
+    }
+
+    // Tokens lost by depositing `deposit` and immediately withdrawing the minted LP tokens, with
+    // any staked tokens paid out valued at the current price. The pool charges no deposit or
+    // withdrawal fee, so this is only the rounding on the mint and the payout.
+    pub fn round_trip_cost(&self, deposit: TokenAmount) -> Result<f64, Error> {
+        let mut simulated_pool = self.hypothetical();
+        let lp_supply_before = simulated_pool.lp_token_amount.0;
+        simulated_pool.add_liquidity(deposit)?;
+        let lp_minted = LpTokenAmount(simulated_pool.lp_token_amount.0 - lp_supply_before);
+        if lp_minted.0 == 0 {
+            return Ok(deposit.0 as f64 / SCALE as f64);
+        }
+
+        let (tokens_out, staked_out) = simulated_pool.burn_lp(lp_minted)?;
+        let price_f64 = self.price.0 as f64 / SCALE as f64;
+        Ok(deposit.0 as f64 / SCALE as f64 - tokens_out - staked_out * price_f64)
+    }
+
+    // Whole days `deposit` must stay in the pool for its share of `daily_fee_income`, the fees
+    // collected by the whole pool per day, to offset its `round_trip_cost`.
+    pub fn breakeven_period(
+        &self,
+        deposit: TokenAmount,
+        daily_fee_income: f64,
+    ) -> Result<u64, Error> {
+        if !daily_fee_income.is_finite() || daily_fee_income <= 0.0 {
+            return Err(Error::InvalidInput);
+        }
+
+        let cost = self.round_trip_cost(deposit)?;
+        if cost <= 0.0 {
+            return Ok(0);
+        }
+
+        let deposit_f64 = deposit.0 as f64 / SCALE as f64;
+        let share = deposit_f64 / (self.total_value() + deposit_f64);
+        let days = (cost / (daily_fee_income * share)).ceil();
+        if days >= u64::MAX as f64 {
+            return Err(Error::Overflow);
+        }
+        Ok(days as u64)
+    }
+
+    // Add liquidity on behalf of `depositor`, crediting the minted LP tokens to their holdings.
+    pub fn add_liquidity_for(
+        &mut self,
+        depositor: Address,
+        token_amount: TokenAmount,
+    ) -> Result<f64, Error> {
+        let lp_supply_before = self.lp_token_amount.0;
+        let lp_tokens_minted = self.add_liquidity(token_amount)?;
+
+        let holding = self
+            .lp_holdings
+            .entry(depositor)
+            .or_insert(LpTokenAmount(0));
+        holding.0 += self.lp_token_amount.0 - lp_supply_before;
+
+        Ok(lp_tokens_minted)
+    }
+
+    // Remove liquidity on behalf of `depositor`, burning LP tokens from their holdings.
+    pub fn remove_liquidity_for(
+        &mut self,
+        depositor: Address,
+        lp_token_amount: LpTokenAmount,
+    ) -> Result<(f64, f64), Error> {
+        let holding = self
+            .lp_holdings
+            .get(&depositor)
+            .map_or(0, |holding| holding.0);
+        if lp_token_amount.0 > holding {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        let result = self.burn_lp(lp_token_amount)?;
+
+        if holding == lp_token_amount.0 {
+            self.lp_holdings.remove(&depositor);
+        } else if let Some(holding) = self.lp_holdings.get_mut(&depositor) {
+            holding.0 -= lp_token_amount.0;
+        }
+
+        Ok(result)
+    }
+
+    // LP tokens credited to `depositor` through `add_liquidity_for`.
+    pub fn lp_holding(&self, depositor: Address) -> LpTokenAmount {
+        self.lp_holdings
+            .get(&depositor)
+            .copied()
+            .unwrap_or(LpTokenAmount(0))
+    }
+
+    // LP tokens not credited to any depositor, the most `remove_liquidity` can burn.
+    pub fn untracked_lp_supply(&self) -> LpTokenAmount {
+        let tracked: Balance = self.lp_holdings.values().map(|holding| holding.0).sum();
+        LpTokenAmount(self.lp_token_amount.0.saturating_sub(tracked))
+    }
+
+    // Herfindahl-style concentration of LP ownership: the sum of each tracked depositor's
+    // squared share of the total LP supply, at most 1.0. Untracked LP tokens are treated as fully
+    // dispersed.
+    pub fn lp_concentration(&self) -> f64 {
+        if self.lp_token_amount.0 == 0 {
+            return 0.0;
+        }
+
+        let lp_supply = self.lp_token_amount.0 as f64;
+        let concentration: f64 = self
+            .lp_holdings
+            .values()
+            .map(|holding| (holding.0 as f64 / lp_supply).powi(2))
+            .sum();
+        concentration.min(1.0)
+    }
+
+    // Compute the fee that would apply if the pool held the given token balance,
+    // as decided by the pool's fee strategy (`LinearFeeStrategy` unless replaced).
+    pub fn fee_at_balance(&self, hypothetical_balance: TokenAmount) -> Percentage {
+        self.fee_strategy.fee(
+            hypothetical_balance,
+            self.effective_liquidity_target(),
+            self.min_fee,
+            self.max_fee,
+        )
+    }
+
+    // Total value held by the pool, with staked tokens valued in tokens at the current price.
+    pub fn total_value(&self) -> f64 {
+        let token_amount_f64 = self.token_amount.0 as f64 / SCALE as f64;
+        let staked_amount_f64 = self.st_token_amount.0 as f64 / SCALE as f64;
+        let price_f64 = self.price.0 as f64 / SCALE as f64;
+
+        token_amount_f64 + staked_amount_f64 * price_f64
+    }
+
+    // `total_value` converted to a reference currency at `reference_rate` units per token.
+    pub fn tvl_in(&self, reference_rate: f64) -> f64 {
+        self.total_value() * reference_rate
+    }
+
+    // Value claimable by all LP holders, in tokens at the current price, saturating at
+    // `Balance::MAX`. The pool takes no protocol fee, so LPs are owed everything it holds.
+    pub fn total_liabilities(&self) -> TokenAmount {
+        TokenAmount(
+            self.token_amount
+                .0
+                .saturating_add(self.value_in_tokens(self.st_token_amount).0),
+        )
+    }
+
+    // Change in `total_value` per unit change in price, which is the staked token balance.
+    pub fn price_delta(&self) -> f64 {
+        self.st_token_amount.0 as f64 / SCALE as f64
+    }
+
+    // Value of a staked token amount in tokens at the current price, saturating at `Balance::MAX`.
+    pub fn value_in_tokens(&self, staked: StakedTokenAmount) -> TokenAmount {
+        let value = (staked.0 as u128).checked_mul(self.price.0 as u128);
+        TokenAmount(value.map_or(Balance::MAX, |value| {
+            saturate_balance(value / SCALE as u128)
+        }))
+    }
+
+    // Amount of staked tokens worth the given token amount at the current price, saturating at
+    // `Balance::MAX`. Fails with `Error::InvalidInput` at a zero price, where no amount is.
+    pub fn value_in_staked(&self, tokens: TokenAmount) -> Result<StakedTokenAmount, Error> {
+        if self.price.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+        let staked = (tokens.0 as u128).checked_mul(SCALE as u128);
+        Ok(StakedTokenAmount(staked.map_or(Balance::MAX, |staked| {
+            saturate_balance(staked / self.price.0 as u128)
+        })))
+    }
+
+    // Percentage by which every existing LP token's share of the pool shrinks if `deposit` is added.
+    pub fn dilution(&self, deposit: TokenAmount) -> Result<Percentage, Error> {
+        if deposit.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+        if self.lp_token_amount.0 == 0 {
+            // Nobody holds LP tokens yet, so there is no one to dilute
+            return Ok(Percentage(0));
+        }
+
+        // LP tokens are minted in proportion to the deposit relative to the pool's total value
+        let deposit = deposit.0 as u128;
+        let pool_value = (self.token_amount.0 as u128)
+            .checked_add(self.value_in_tokens(self.st_token_amount).0 as u128)
+            .and_then(|pool_value| pool_value.checked_add(deposit))
+            .ok_or(Error::Overflow)?;
+        let dilution = deposit.checked_mul(SCALE as u128).ok_or(Error::Overflow)? / pool_value;
+        Ok(Percentage(dilution as u64))
+    }
+
+    // Annualize fee income collected over `period_secs` relative to the pool's total value.
+    pub fn lp_fee_apr(&self, fees_collected: f64, period_secs: u64) -> f64 {
+        let total_value = self.total_value();
+        if total_value == 0.0 || period_secs == 0 {
+            return 0.0;
+        }
+
+        fees_collected / total_value * (SECONDS_PER_YEAR as f64 / period_secs as f64)
+    }
+
+    // Value of a single LP token in tokens. An empty pool prices LP tokens at one token each.
+    pub fn lp_token_price(&self) -> f64 {
+        if self.lp_token_amount.0 == 0 {
+            return 1.0;
+        }
+
+        self.total_value() / (self.lp_token_amount.0 as f64 / SCALE as f64)
+    }
+
+    // Value of an LP position in staked tokens at the current price: its token value at
+    // `lp_token_price`, converted like `value_in_staked`. Fails with `Error::InvalidInput` at
+    // a zero price, where staked tokens cannot denominate anything.
+    pub fn lp_value_in_staked(&self, lp: LpTokenAmount) -> Result<f64, Error> {
+        if self.price.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+
+        let token_value = lp.0 as f64 / SCALE as f64 * self.lp_token_price();
+        Ok(token_value / (self.price.0 as f64 / SCALE as f64))
+    }
+
+    // LP tokens minted per token deposited at the current state. Deposits mint against the
+    // pool's total value, so this is the LP supply over the token balance only while the pool
+    // holds no staked tokens. An empty pool mints 1:1.
+    pub fn marginal_mint_rate(&self) -> f64 {
+        let total_value = self.total_value();
+        if self.lp_token_amount.0 == 0 || total_value == 0.0 {
+            return 1.0;
+        }
+
+        self.lp_token_amount.0 as f64 / SCALE as f64 / total_value
+    }
+
+    // Price at which a swap of `staked_token_amount` would leave the LP token price unchanged,
+    // i.e. the price move that exactly offsets the fee the swap leaves in the pool.
+    pub fn price_to_preserve_lp_value(
+        &self,
+        staked_token_amount: StakedTokenAmount,
+    ) -> Result<Price, Error> {
+        let value_before = (self.token_amount.0 as u128)
+            .checked_add(self.value_in_tokens(self.st_token_amount).0 as u128)
+            .ok_or(Error::Overflow)?;
+
+        let mut simulated_pool = self.hypothetical();
+        simulated_pool.swap(staked_token_amount)?;
+
+        // Solve `token_amount + st_token_amount * price = value_before` for the price
+        let staked_value = value_before
+            .checked_sub(simulated_pool.token_amount.0 as u128)
+            .ok_or(Error::InvalidInput)?;
+        let price = mul_div(
+            staked_value,
+            SCALE as u128,
+            simulated_pool.st_token_amount.0 as u128,
+        )
+        .ok_or(Error::Overflow)?;
+        u64::try_from(price).map(Price).map_err(|_| Error::Overflow)
+    }
+
+    // LP tokens a deposit of the remaining headroom under `max_token_amount` would mint.
+    // Returns `LpTokenAmount(Balance::MAX)` when the pool is uncapped.
+    pub fn max_mintable_lp(&self) -> LpTokenAmount {
+        let Some(max_token_amount) = self.max_token_amount else {
+            return LpTokenAmount(Balance::MAX);
+        };
+
+        let headroom = max_token_amount.0.saturating_sub(self.token_amount.0) as u128;
+        let pool_value = (self.token_amount.0 as u128)
+            .saturating_add(self.value_in_tokens(self.st_token_amount).0 as u128);
+        if self.lp_token_amount.0 == 0 || pool_value == 0 {
+            // The first deposit mints LP tokens 1:1
+            return LpTokenAmount(headroom as Balance);
+        }
+
+        LpTokenAmount(
+            mul_div(headroom, self.lp_token_amount.0 as u128, pool_value)
+                .map_or(Balance::MAX, saturate_balance),
+        )
+    }
+
+    // This pool's LP tokens per LP token of `other` when `other` is absorbed into this pool, so
+    // that every holder keeps the same value. Both LP tokens are valued at this pool's price.
+    pub fn lp_conversion_rate(&self, other: &LpPool) -> f64 {
+        other.at_price(self.price).lp_token_price() / self.lp_token_price()
+    }
+
+    // Scaled token and staked token amounts backing one whole LP token, computed in integers.
+    // An empty pool backs each LP token with exactly one token, matching the first-deposit anchoring.
+    pub fn backing_per_lp(&self) -> (TokenAmount, StakedTokenAmount) {
+        if self.lp_token_amount.0 == 0 {
+            return (TokenAmount(SCALE as Balance), StakedTokenAmount(0));
+        }
+
+        let per_lp = |amount: Balance| {
+            mul_div(
+                amount as u128,
+                SCALE as u128,
+                self.lp_token_amount.0 as u128,
+            )
+            .map_or(Balance::MAX, saturate_balance)
+        };
+        (
+            TokenAmount(per_lp(self.token_amount.0)),
+            StakedTokenAmount(per_lp(self.st_token_amount.0)),
+        )
+    }
+
+    // LP token price if the staked token price moved to `new_price`, without updating the pool.
+    // LP supply stays fixed, so the LP price moves with the staked share of the pool's value.
+    pub fn lp_price_after(&self, new_price: Price) -> f64 {
+        self.at_price(new_price).lp_token_price()
+    }
+
+    // Return a copy of the pool with the price overridden, leaving the original untouched.
+    pub fn at_price(&self, price: Price) -> LpPool {
+        LpPool {
+            price,
+            ..self.hypothetical()
+        }
+    }
+
+    // Move `fraction` of every balance into a new pool sharing this pool's parameters: price,
+    // fees and fee strategy, liquidity target, deposit limits, staked reserve, price age limit,
+    // fee waiver and rebates. The new pool starts with a fresh operation log, stats and
+    // idempotency keys, and without a metrics sink.
+    pub fn split(&mut self, fraction: Percentage) -> Result<LpPool, Error> {
+        self.ensure_not_paused()?;
+        if fraction.0 > SCALE {
+            return Err(Error::InvalidInput);
+        }
+
+        // At most the whole amount, so the quotient always fits
+        let share_of = |amount: Balance| {
+            mul_div(amount as u128, fraction.0 as u128, SCALE as u128)
+                .map_or(amount, saturate_balance)
+        };
+        let mut new_pool = LpPool {
+            token_amount: TokenAmount(share_of(self.token_amount.0)),
+            st_token_amount: StakedTokenAmount(share_of(self.st_token_amount.0)),
+            lp_token_amount: LpTokenAmount(share_of(self.lp_token_amount.0)),
+            applied_swap_keys: VecDeque::with_capacity(IDEMPOTENCY_KEY_CAPACITY),
+            operation_count: 0,
+            lp_holdings: HashMap::new(),
+            operation_log: Vec::new(),
+            stats: PoolStats::empty(),
+            last_op_pnl: 0.0,
+            staked_cost_basis: TokenAmount(0),
+            staked_acquisition_value: TokenAmount(0),
+            net_deposits: TokenAmount(0),
+            ..self.hypothetical()
+        };
+        (
+            new_pool.staked_cost_basis,
+            new_pool.staked_acquisition_value,
+        ) = self.take_cost_basis(new_pool.st_token_amount);
+        new_pool.net_deposits.0 = share_of(self.net_deposits.0);
+        self.net_deposits.0 -= new_pool.net_deposits.0;
+        // Tracked depositors hold the same share of both pools' supply as they did before
+        for (address, holding) in &mut self.lp_holdings {
+            let moved = share_of(holding.0);
+            holding.0 -= moved;
+            if moved > 0 {
+                new_pool.lp_holdings.insert(*address, LpTokenAmount(moved));
+            }
+        }
+        self.lp_holdings.retain(|_, holding| holding.0 > 0);
+
+        self.token_amount.0 -= new_pool.token_amount.0;
+        self.st_token_amount.0 -= new_pool.st_token_amount.0;
+        self.lp_token_amount.0 -= new_pool.lp_token_amount.0;
+        self.record_backing_reference();
+        new_pool.record_backing_reference();
+
+        Ok(new_pool)
+    }
+
+    // Remember the current balances and LP supply as the backing `repair_lp_supply` restores.
+    fn record_backing_reference(&mut self) {
+        self.backing_reference = (
+            self.token_amount,
+            self.st_token_amount,
+            self.lp_token_amount,
+        );
+    }
+
+    // Recompute the LP supply from the current balances so every LP token is backed as it was
+    // after the last operation, e.g. after a migration rescaled the balances. Both balances
+    // must have moved by the same factor since; otherwise the supply cannot be recovered and
+    // this fails with `Error::InvariantViolation`, leaving the pool untouched.
+    pub fn repair_lp_supply(&mut self) -> Result<(), Error> {
+        let (reference_tokens, reference_staked, reference_lp) = self.backing_reference;
+        // Supply implied by one balance: `Some(None)` if it was and still is empty, so it says
+        // nothing, and `None` if it was empty but no longer is, which no operation could cause
+        let supply_from = |amount: Balance, reference: Balance| {
+            if reference == 0 {
+                return (amount == 0).then_some(None);
+            }
+            // A supply past `u128::MAX` fails the conversion to `Balance` below all the same
+            Some(Some(
+                mul_div(amount as u128, reference_lp.0 as u128, reference as u128)
+                    .unwrap_or(u128::MAX),
+            ))
+        };
+
+        let from_tokens = supply_from(self.token_amount.0, reference_tokens.0);
+        let from_staked = supply_from(self.st_token_amount.0, reference_staked.0);
+        let lp_supply = match (from_tokens, from_staked) {
+            (Some(Some(from_tokens)), Some(Some(from_staked)))
+                if from_tokens.abs_diff(from_staked) <= 1 =>
+            {
+                from_tokens.min(from_staked)
+            }
+            (Some(Some(lp_supply)), Some(None)) | (Some(None), Some(Some(lp_supply))) => lp_supply,
+            (Some(None), Some(None)) => 0,
+            _ => return Err(Error::InvariantViolation),
+        };
+
+        self.lp_token_amount =
+            LpTokenAmount(Balance::try_from(lp_supply).map_err(|_| Error::Overflow)?);
+        self.record_backing_reference();
+        Ok(())
+    }
+
+    // Remove the share of the staked cost basis and acquisition value attributable to
+    // `staked_removed`, which must be taken out of the staked balance right after. Returns the
+    // removed cost basis and acquisition value.
+    fn take_cost_basis(&mut self, staked_removed: StakedTokenAmount) -> (TokenAmount, TokenAmount) {
+        if self.st_token_amount.0 == 0 {
+            return (TokenAmount(0), TokenAmount(0));
+        }
+
+        // `staked_removed` is at most the staked balance, so the quotient always fits
+        let share_of = |amount: Balance| {
+            mul_div(
+                amount as u128,
+                staked_removed.0 as u128,
+                self.st_token_amount.0 as u128,
+            )
+            .map_or(amount, saturate_balance)
+        };
+        let cost_basis = share_of(self.staked_cost_basis.0);
+        let acquisition_value = share_of(self.staked_acquisition_value.0);
+        self.staked_cost_basis.0 -= cost_basis;
+        self.staked_acquisition_value.0 -= acquisition_value;
+        (TokenAmount(cost_basis), TokenAmount(acquisition_value))
+    }
+
+    // Weighted-average price, in tokens per staked token, the pool paid for its staked balance.
+    // Withdrawals take out cost basis pro rata, so they leave the average unchanged, while
+    // accrued rewards cost nothing and lower it. 0 when the pool holds no staked tokens.
+    pub fn staked_cost_basis(&self) -> f64 {
+        if self.st_token_amount.0 == 0 {
+            return 0.0;
+        }
+
+        self.staked_cost_basis.0 as f64 / self.st_token_amount.0 as f64
+    }
+
+    // Fees accrued on the staked balance, split into realized and unrealized parts in tokens.
+    // Realized fees are the discount the pool got when acquiring staked tokens, valued at the
+    // price at the time; unrealized fees are the price moves since. Both together equal the
+    // current value of the staked balance less the tokens paid for it.
+    pub fn fee_split(&self) -> (f64, f64) {
+        let current_value = self.value_in_tokens(self.st_token_amount).0 as f64;
+        let acquisition_value = self.staked_acquisition_value.0 as f64;
+        let realized = acquisition_value - self.staked_cost_basis.0 as f64;
+        let unrealized = current_value - acquisition_value;
+
+        (realized / SCALE as f64, unrealized / SCALE as f64)
+    }
+
+    // Fee an infinitesimal swap would pay at the current state.
+    pub fn marginal_fee(&self) -> Percentage {
+        self.fee_at_balance(self.token_amount)
+    }
+
+    // Fees, in tokens, accrued by swapping continuously while the token balance falls from
+    // `balance_start` to `balance_end` along the pool's fee strategy. Paying out `db` tokens at
+    // fee `f` takes a gross `db / (1 - f)`, of which `f / (1 - f) * db` is fee, so this
+    // integrates `f / (1 - f)` numerically through `fee_at_balance`, split at the liquidity
+    // target where the built-in curves bend. Returns infinity if the fee reaches 100% inside the
+    // range.
+    pub fn fee_integral(&self, balance_start: TokenAmount, balance_end: TokenAmount) -> f64 {
+        if balance_end.0 >= balance_start.0 {
+            return 0.0;
+        }
+
+        let start = balance_start.0 as f64;
+        let end = balance_end.0 as f64;
+        let target = self.effective_liquidity_target().0 as f64;
+
+        let fees = if end < target && target < start {
+            self.fee_gross_up_integral(target, start) + self.fee_gross_up_integral(end, target)
+        } else {
+            self.fee_gross_up_integral(end, start)
+        };
+        fees / SCALE as f64
+    }
+
+    // Composite Simpson's rule for `f / (1 - f)` over scaled balances `low..high`.
+    fn fee_gross_up_integral(&self, low: f64, high: f64) -> f64 {
+        const STEPS: u32 = 1000;
+        let gross_up = |balance: f64| {
+            let fee = self
+                .fee_at_balance(TokenAmount(balance.round() as Balance))
+                .to_f64();
+            fee / (1.0 - fee)
+        };
+
+        let step = (high - low) / STEPS as f64;
+        let mut sum = gross_up(low) + gross_up(high);
+        for i in 1..STEPS {
+            let weight = if i % 2 == 1 { 4.0 } else { 2.0 };
+            sum += weight * gross_up(low + step * i as f64);
+        }
+        sum * step / 3.0
+    }
+
+    // Marginal fee after each price in `prices` is applied in turn to a copy of the pool.
+    // A liquidity target denominated in tokens keeps the price alone from moving the fee; one set
+    // as a share of total value moves with the price.
+    pub fn simulate_price_path(&self, prices: &[Price]) -> Vec<Percentage> {
+        let mut simulated_pool = self.hypothetical();
+        prices
+            .iter()
+            .map(|price| {
+                simulated_pool.price = *price;
+                simulated_pool.marginal_fee()
+            })
+            .collect()
+    }
+
+    // Spot price reduced by the marginal fee at the current token balance.
+    pub fn fee_adjusted_price(&self) -> f64 {
+        let price_f64 = self.price.0 as f64 / SCALE as f64;
+        let marginal_fee = self.marginal_fee().to_f64();
+
+        price_f64 * (1.0 - marginal_fee)
+    }
+
+    // Staked tokens available to be paid out, i.e. the staked balance above the reserved floor.
+    pub fn available_staked(&self) -> StakedTokenAmount {
+        StakedTokenAmount(self.st_token_amount.0.saturating_sub(self.staked_reserve.0))
+    }
+
+    // Seconds elapsed between the pool's creation and `now`.
+    pub fn age(&self, now: u64) -> u64 {
+        now.saturating_sub(self.created_at)
+    }
+
+    // Number of successful liquidity and swap operations processed by the pool.
+    pub fn operation_count(&self) -> u64 {
+        self.operation_count
+    }
+
+    // Share of the pool's total value held as plain tokens, with staked tokens valued at the current price.
+    pub fn composition(&self) -> Percentage {
+        self.composition_of(self.token_amount, self.st_token_amount)
+    }
+
+    // `composition` for a pool holding the given balances at the current price.
+    fn composition_of(
+        &self,
+        token_amount: TokenAmount,
+        st_token_amount: StakedTokenAmount,
+    ) -> Percentage {
+        let token_amount = token_amount.0 as u128;
+        let pool_value =
+            token_amount.saturating_add(self.value_in_tokens(st_token_amount).0 as u128);
+        if pool_value == 0 {
+            return Percentage(0);
+        }
+
+        Percentage(
+            mul_div(token_amount, SCALE as u128, pool_value).map_or(SCALE, |share| share as u64),
+        )
+    }
+
+    // Split `total_value` tokens' worth of deposit into tokens and staked tokens in the same
+    // proportions as the pool's `composition`, so depositing both leaves it unchanged. An empty
+    // pool, whose first deposit is in tokens, takes it all in tokens.
+    pub fn optimal_deposit_split(
+        &self,
+        total_value: TokenAmount,
+    ) -> (TokenAmount, StakedTokenAmount) {
+        let pool_value = self.total_liabilities().0 as u128;
+        if pool_value == 0 {
+            return (total_value, StakedTokenAmount(0));
+        }
+
+        // The token balance is part of the pool's value, so the share never exceeds `total_value`
+        let token_share = mul_div(
+            total_value.0 as u128,
+            self.token_amount.0 as u128,
+            pool_value,
+        )
+        .map_or(total_value.0, saturate_balance);
+        let staked_share = self
+            .value_in_staked(TokenAmount(total_value.0 - token_share))
+            .unwrap_or(StakedTokenAmount(0));
+        (TokenAmount(token_share), staked_share)
+    }
+
+    // Staked token amount to swap in so that the pool's composition falls to `target_ratio`.
+    // Swapping staked tokens in can only lower the token share, so the target must not exceed the current one.
+    // Fails with `Error::InsufficientLiquidity` if no swap the pool can service reaches the target.
+    pub fn rebalance_swap(&self, target_ratio: Percentage) -> Result<StakedTokenAmount, Error> {
+        if target_ratio.0 > SCALE || target_ratio > self.composition() || self.price.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+
+        // Priced like `swap`, but applied to the balances only
+        let composition_after = |staked_amount: Balance| -> Result<Percentage, Error> {
+            if staked_amount == 0 {
+                return Ok(self.composition());
+            }
+            let quote = self.quote_swap(StakedTokenAmount(staked_amount))?;
+            let token_amount = self
+                .token_amount
+                .0
+                .checked_sub(quote.tokens_to_receive_scaled)
+                .ok_or(Error::InsufficientLiquidity)?;
+            let st_token_amount = self
+                .st_token_amount
+                .0
+                .checked_add(staked_amount)
+                .ok_or(Error::Overflow)?;
+            Ok(self.composition_of(
+                TokenAmount(token_amount),
+                StakedTokenAmount(st_token_amount),
+            ))
+        };
+
+        // Binary search for the largest swap that keeps the composition at or above the target
+        let ceiling = Balance::MAX / 2;
+        let (mut low, mut high): (Balance, Balance) = (0, ceiling);
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            match composition_after(mid) {
+                Ok(composition) if composition >= target_ratio => low = mid,
+                _ => high = mid - 1,
+            }
+        }
+
+        // The target is reached if the found swap lands on it or one more unit crosses it;
+        // otherwise the search stopped at the ceiling or where the pool runs out of liquidity
+        let reached = low < ceiling
+            && (composition_after(low) == Ok(target_ratio)
+                || matches!(composition_after(low + 1), Ok(composition) if composition < target_ratio));
+        if !reached {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        Ok(StakedTokenAmount(low))
+    }
+
+    // Marginal prices, in tokens per staked token, for an infinitesimal swap in each direction.
+    // Returns `(staked_to_token, token_to_staked)`: selling staked tokens yields spot minus the
+    // marginal fee, while buying staked tokens with tokens costs spot plus the marginal fee.
+    pub fn marginal_prices(&self) -> (f64, f64) {
+        let price_f64 = self.price.0 as f64 / SCALE as f64;
+        let marginal_fee = self.marginal_fee().to_f64();
+
+        (
+            price_f64 * (1.0 - marginal_fee),
+            price_f64 * (1.0 + marginal_fee),
+        )
+    }
+
+    // `points` evenly spaced balances from 0 to twice the liquidity target, both ends included,
+    // with the fee at each, for plotting the fee curve.
+    pub fn sample_fee_curve(&self, points: usize) -> Vec<(TokenAmount, Percentage)> {
+        let range = (self.effective_liquidity_target().0 as u128).saturating_mul(2);
+        let intervals = points.saturating_sub(1).max(1) as u128;
+        (0..points as u128)
+            .map(|index| {
+                let balance = TokenAmount(saturate_balance(
+                    range / intervals * index + range % intervals * index / intervals,
+                ));
+                (balance, self.fee_at_balance(balance))
+            })
+            .collect()
+    }
+
+    // Check the fee strategy against its contract on `samples` points of the fee curve (see
+    // `sample_fee_curve`), failing with `Error::InvariantViolation` if the fee rises anywhere
+    // as the balance grows.
+    pub fn validate_fee_monotonic(&self, samples: usize) -> Result<(), Error> {
+        let fee_curve = self.sample_fee_curve(samples);
+        if fee_curve.windows(2).any(|pair| pair[1].1 > pair[0].1) {
+            return Err(Error::InvariantViolation);
+        }
+        Ok(())
+    }
+
+    // Token balance segmented by the fee paid as it drains, current fee first: each entry is
+    // the highest fee charged within a tier and the tokens that can flow out before the fee
+    // rises by `fee_step` or more over the tier's first fee. The fee is decided by the balance
+    // left after each token paid out, and the amounts add up to the full balance. A fee curve
+    // that never rises as the balance grows therefore yields at most one tier per `fee_step`
+    // of its fee range, plus one; a zero step is treated as the smallest `Percentage` unit,
+    // which yields a tier per distinct fee.
+    pub fn outflow_by_fee_tier(&self, fee_step: Percentage) -> Vec<(Percentage, TokenAmount)> {
+        let fee_step = fee_step.0.max(1);
+        let mut tiers = Vec::new();
+        let mut tier_top = self.token_amount.0;
+        while tier_top > 0 {
+            // Binary search for the lowest balance charged less than a step above the tier's first fee
+            let fee_ceiling = self
+                .fee_at_balance(TokenAmount(tier_top - 1))
+                .0
+                .saturating_add(fee_step);
+            let (mut low, mut high) = (0, tier_top - 1);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if self.fee_at_balance(TokenAmount(mid)).0 < fee_ceiling {
+                    high = mid;
+                } else {
+                    low = mid + 1;
+                }
+            }
+
+            tiers.push((
+                self.fee_at_balance(TokenAmount(low)),
+                TokenAmount(tier_top - low),
+            ));
+            tier_top = low;
+        }
+
+        tiers
+    }
+
+    // External prices between which no arbitrage against the pool is profitable: selling staked
+    // tokens to the pool pays `marginal_prices().0`, buying them costs `marginal_prices().1`,
+    // so only external prices outside that band leave a profit after the fee.
+    pub fn no_arb_band(&self) -> (f64, f64) {
+        self.marginal_prices()
+    }
+
+    // Largest LP burn that keeps the token balance at or above the liquidity target,
+    // i.e. the biggest withdrawal that does not push swaps into the elevated fee regime.
+    pub fn max_remove_before_elevated(&self) -> LpTokenAmount {
+        let token_amount = self.token_amount.0 as u128;
+        let liquidity_target = self.effective_liquidity_target().0 as u128;
+        if token_amount <= liquidity_target {
+            return LpTokenAmount(0);
+        }
+
+        let lp_tokens = mul_div(
+            self.lp_token_amount.0 as u128,
+            token_amount - liquidity_target,
+            token_amount,
+        );
+        LpTokenAmount(lp_tokens.map_or(self.lp_token_amount.0, saturate_balance))
+    }
+
+    // LP tokens to burn so that the withdrawal brings `reserve_ratio` down to `target_ratio`.
+    // Burning can only lower the ratio, and only against a fixed liquidity target: one set as a
+    // share of total value shrinks with the pool and leaves the ratio unchanged.
+    pub fn lp_burn_for_ratio(&self, target_ratio: f64) -> Result<LpTokenAmount, Error> {
+        if !target_ratio.is_finite()
+            || target_ratio < 0.0
+            || target_ratio > self.reserve_ratio()
+            || self.liquidity_target_fraction.is_some()
+        {
+            return Err(Error::InvalidInput);
+        }
+
+        // Withdrawals pay out tokens pro rata, so burn the share of the balance above the new one
+        let token_amount = self.token_amount.0 as f64;
+        let tokens_to_keep = target_ratio * self.liquidity_target.0 as f64;
+        let burn_share = (token_amount - tokens_to_keep) / token_amount;
+        Ok(LpTokenAmount(
+            (burn_share * self.lp_token_amount.0 as f64) as Balance,
+        ))
+    }
+
+    // Number of reverse swaps, each buying `recovery_swap` staked tokens from the pool with
+    // tokens, needed to bring the token balance back to the liquidity target after burning
+    // `withdraw_lp`. Simulated on a copy of the pool; reverse swaps are priced like `quote_both`.
+    // Every swap but the last leaves the balance below the target, where the fee changes with
+    // each swap, so they are simulated one by one; recovery swaps tiny relative to the shortfall
+    // fail with `Error::SimulationLimitExceeded` after `MAX_SIMULATED_SWAPS`.
+    pub fn simulate_shock(
+        &self,
+        withdraw_lp: LpTokenAmount,
+        recovery_swap: StakedTokenAmount,
+    ) -> Result<u64, Error> {
+        if recovery_swap.0 == 0 || self.price.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+
+        let mut simulated_pool = self.hypothetical();
+        simulated_pool.burn_lp(withdraw_lp)?;
+        let mut swaps = 0;
+        while simulated_pool.token_amount.0 < simulated_pool.effective_liquidity_target().0 {
+            if recovery_swap.0 > simulated_pool.available_staked().0 {
+                return Err(Error::InsufficientLiquidity);
+            }
+            if swaps == MAX_SIMULATED_SWAPS {
+                return Err(Error::SimulationLimitExceeded);
+            }
+
+            let gross_tokens = simulated_pool.value_in_tokens(recovery_swap);
+            let fee = simulated_pool.fee_at_balance(TokenAmount(
+                simulated_pool.token_amount.0.saturating_add(gross_tokens.0),
+            ));
+            let tokens_in = mul_div(
+                gross_tokens.0 as u128,
+                (SCALE + fee.0) as u128,
+                SCALE as u128,
+            )
+            .unwrap_or(u128::MAX);
+            simulated_pool.take_cost_basis(recovery_swap);
+            simulated_pool.token_amount.0 = simulated_pool
+                .token_amount
+                .0
+                .checked_add(saturate_balance(tokens_in))
+                .ok_or(Error::Overflow)?;
+            simulated_pool.st_token_amount.0 -= recovery_swap.0;
+            swaps += 1;
+        }
+
+        Ok(swaps)
+    }
+
+    // Token balance relative to the liquidity target; below 1.0 swaps pay elevated fees.
+    // Infinite when no liquidity target is configured.
+    pub fn reserve_ratio(&self) -> f64 {
+        let liquidity_target = self.effective_liquidity_target();
+        if liquidity_target.0 == 0 {
+            return f64::INFINITY;
+        }
+
+        self.token_amount.0 as f64 / liquidity_target.0 as f64
+    }
+
+    // Staked token notional at the current price relative to the plain token balance.
+    // Infinite when staked tokens are held against no tokens, and 0 for an empty pool.
+    pub fn leverage(&self) -> f64 {
+        let staked_notional = self.value_in_tokens(self.st_token_amount).0;
+        if self.token_amount.0 == 0 {
+            return if staked_notional == 0 {
+                0.0
+            } else {
+                f64::INFINITY
+            };
+        }
+
+        staked_notional as f64 / self.token_amount.0 as f64
+    }
+
+    // Invariant `k = x * y` of a constant-product pool holding the same balances, with both
+    // balances scaled down to natural values. For comparison only; this pool does not keep it.
+    pub fn equivalent_k(&self) -> f64 {
+        let token_amount_f64 = self.token_amount.0 as f64 / SCALE as f64;
+        let staked_amount_f64 = self.st_token_amount.0 as f64 / SCALE as f64;
+
+        token_amount_f64 * staked_amount_f64
+    }
+
+    // Spot price, in tokens per staked token, of a constant-product pool holding the same
+    // balances. Infinite when tokens are held against no staked tokens, and 0 for an empty pool.
+    pub fn equivalent_price(&self) -> f64 {
+        if self.st_token_amount.0 == 0 {
+            return if self.token_amount.0 == 0 {
+                0.0
+            } else {
+                f64::INFINITY
+            };
+        }
+
+        self.token_amount.0 as f64 / self.st_token_amount.0 as f64
+    }
+
+    // Whole seconds until swaps paying out `swap_rate_per_sec` tokens per second bring the token
+    // balance down to the effective liquidity target, rounded up. `None` if the balance is
+    // already below the target or the rate never drains it.
+    pub fn time_to_target(&self, swap_rate_per_sec: f64) -> Option<u64> {
+        let target = self.effective_liquidity_target();
+        if self.token_amount.0 < target.0
+            || !swap_rate_per_sec.is_finite()
+            || swap_rate_per_sec <= 0.0
+        {
+            return None;
+        }
+
+        let gap = (self.token_amount.0 - target.0) as f64 / SCALE as f64;
+        Some((gap / swap_rate_per_sec).ceil() as u64)
+    }
+
+    // Largest share of the token balance a single swap can take out, over swaps worth at most
+    // the whole balance. A swap worth `g` tokens pays out `g * (1 - f)` with the fee taken from
+    // `fee_at_balance` at the balance left after `g`; the best `g` is found on a coarse grid and
+    // refined around the winner. Lower values mean more resistance.
+    pub fn drain_resistance(&self) -> f64 {
+        const STEPS: Balance = 1000;
+        let balance = self.token_amount.0;
+        if balance == 0 {
+            return 0.0;
+        }
+
+        let payout = |gross: Balance| {
+            gross as f64 * (1.0 - self.fee_at_balance(TokenAmount(balance - gross)).to_f64())
+        };
+        let best_of = |best: Balance, gross: Balance| {
+            if payout(gross) > payout(best) {
+                gross
+            } else {
+                best
+            }
+        };
+
+        let step = (balance / STEPS).max(1);
+        let coarse_best = (0..=STEPS)
+            .map(|i| (i * step).min(balance))
+            .chain(std::iter::once(balance))
+            .fold(0, best_of);
+
+        let fine_step = (step / STEPS).max(1);
+        let fine_end = coarse_best.saturating_add(step).min(balance);
+        let mut best = coarse_best;
+        let mut gross = Some(coarse_best.saturating_sub(step));
+        while let Some(candidate) = gross.filter(|gross| *gross <= fine_end) {
+            best = best_of(best, candidate);
+            gross = candidate.checked_add(fine_step);
+        }
+
+        payout(best) / balance as f64
+    }
+
+    // Fraction by which the price can fall before `total_value` drops below the tokens LPs
+    // deposited. 1.0 when the token balance alone covers the deposits, 0.0 when the pool is
+    // already below them.
+    pub fn max_price_drawdown(&self) -> f64 {
+        let token_amount = self.token_amount.0 as f64;
+        let staked_value = self.value_in_tokens(self.st_token_amount).0 as f64;
+        let net_deposits = self.net_deposits.0 as f64;
+        if token_amount >= net_deposits {
+            return 1.0;
+        }
+
+        // Solve `token_amount + staked_value * (1 - drawdown) = net_deposits`
+        ((token_amount + staked_value - net_deposits) / staked_value).max(0.0)
+    }
+
+    // Collect balances, price, fees and derived metrics into a single summary.
+    pub fn summary(&self) -> PoolSummary {
+        PoolSummary {
+            token_amount: self.token_amount.0 as f64 / SCALE as f64,
+            st_token_amount: self.st_token_amount.0 as f64 / SCALE as f64,
+            lp_token_amount: self.lp_token_amount.0 as f64 / SCALE as f64,
+            price: self.price.0 as f64 / SCALE as f64,
+            min_fee: self.min_fee,
+            max_fee: self.max_fee,
+            liquidity_target: self.effective_liquidity_target().0 as f64 / SCALE as f64,
+            reserve_ratio: self.reserve_ratio(),
+            lp_token_price: self.lp_token_price(),
+        }
+    }
+
+    // Balances and price scaled down to natural values, for comparing pools across scales.
+    pub fn normalized_state(&self) -> NormalizedState {
+        let normalize = |scaled: Balance| scaled as f64 / SCALE as f64;
+        NormalizedState {
+            token_amount: normalize(self.token_amount.0),
+            st_token_amount: normalize(self.st_token_amount.0),
+            lp_token_amount: normalize(self.lp_token_amount.0),
+            staked_reserve: normalize(self.staked_reserve.0),
+            liquidity_target: normalize(self.liquidity_target.0),
+            price: self.price.0 as f64 / SCALE as f64,
+        }
+    }
+
+    // The pool's parameters, without any of its balances.
+    pub fn config(&self) -> PoolConfig {
+        PoolConfig {
+            price: self.price,
+            min_fee: self.min_fee,
+            max_fee: self.max_fee,
+            liquidity_target: self.liquidity_target,
+        }
+    }
+
+    // Value gained by LPs in the most recent liquidity or swap operation, in tokens at the
+    // price at the time: the change in `total_value` net of the value deposited or withdrawn.
+    // For a swap this is the fee it left in the pool. 0 before any operation.
+    pub fn last_op_pnl(&self) -> f64 {
+        self.last_op_pnl
+    }
+
+    // Cumulative swap statistics.
+    pub fn stats(&self) -> PoolStats {
+        self.stats
+    }
+
+    // Clear the cumulative swap statistics, e.g. at the end of a reporting period. Balances,
+    // price and fees are left untouched.
+    pub fn reset_stats(&mut self) {
+        self.stats = PoolStats::empty();
+    }
+
+    // Fees earned per LP token since `reference_fees_accrued`, an earlier reading of
+    // `stats().fees_collected` in natural tokens, for reward-per-token accounting.
+    // 0 when no LP tokens are outstanding.
+    pub fn fees_per_lp_since(&self, reference_fees_accrued: f64) -> f64 {
+        if self.lp_token_amount.0 == 0 {
+            return 0.0;
+        }
+
+        let fees_collected = self.stats.fees_collected.0 as f64 / SCALE as f64;
+        (fees_collected - reference_fees_accrued) / (self.lp_token_amount.0 as f64 / SCALE as f64)
+    }
+
+    // Staked token price implied by the pool's swap flow: the tokens it paid out per staked token
+    // it received. The oracle price is an input rather than something balances settle to, so this
+    // is the only price the pool's own state reveals; it sits below `price` by the fees charged.
+    // Returns `None` until at least one swap has been recorded.
+    pub fn implied_price(&self) -> Option<f64> {
+        if self.stats.staked_volume.0 == 0 {
+            return None;
+        }
+
+        Some(self.stats.tokens_paid.0 as f64 / self.stats.staked_volume.0 as f64)
+    }
+
+    // Return all fee rates configured on the pool.
+    pub fn fee_schedule(&self) -> FeeSchedule {
+        FeeSchedule {
+            swap_min_fee: self.min_fee,
+            swap_max_fee: self.max_fee,
+        }
+    }
+
+    // Staked token input whose swap would push the fee up to `edge`.
+    // The fee depends on the post-swap balance, so this searches the fee curve for the largest
+    // balance at which the fee reaches `edge` and converts the remaining gap to staked tokens.
+    pub fn fee_breakeven_size(&self, edge: Percentage) -> Result<StakedTokenAmount, Error> {
+        if edge < self.min_fee || edge > self.max_fee || self.price.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+        if self.fee_at_balance(self.token_amount) >= edge {
+            // The fee already reaches the edge for any swap size
+            return Ok(StakedTokenAmount(0));
+        }
+        if self.fee_at_balance(TokenAmount(0)) < edge {
+            return Err(Error::InvalidInput);
+        }
+
+        // Binary search for the largest balance whose fee still reaches the edge
+        let (mut low, mut high) = (0, self.token_amount.0);
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if self.fee_at_balance(TokenAmount(mid)) >= edge {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+
+        let staked_amount = mul_div(
+            (self.token_amount.0 - low) as u128,
+            SCALE as u128,
+            self.price.0 as u128,
+        )
+        .ok_or(Error::Overflow)?;
+        Ok(StakedTokenAmount(
+            Balance::try_from(staked_amount).map_err(|_| Error::Overflow)?,
+        ))
+    }
+
+    // Compute the output of swapping staked tokens without touching the pool state.
+    // The quote holds the tokens to receive both as a "natural" f64 value and as the scaled amount.
+    fn quote_swap(&self, staked_token_amount: StakedTokenAmount) -> Result<SwapQuote, Error> {
+        self.quote_swap_with_rebate(staked_token_amount, Percentage(0))
+    }
+
+    // Compute the output of a swap whose fee is reduced by `rebate` (a share of the fee).
+    fn quote_swap_with_rebate(
+        &self,
+        staked_token_amount: StakedTokenAmount,
+        rebate: Percentage,
+    ) -> Result<SwapQuote, Error> {
+        if staked_token_amount.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+        // An empty pool cannot pay out anything, whatever the fee would be
+        if self.token_amount.0 == 0 {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        let staked_amount_f64 = staked_token_amount.0 as f64 / SCALE as f64;
+        let price_f64 = self.price.0 as f64 / SCALE as f64; // Assuming `self.price.0` is already scaled
+
+        // The fee is determined by the token balance left in the pool after the swap.
+        // It is a pure function of the pool state, so replaying the same sequence of
+        // operations on identically initialized pools always yields the same fees.
+        let gross_tokens_scaled = (staked_amount_f64 * price_f64 * SCALE as f64) as Balance;
+        let fee = self.fee_at_balance(TokenAmount(
+            self.token_amount.0.saturating_sub(gross_tokens_scaled),
+        ));
+        let fee = Percentage(
+            (fee.0 as u128 * SCALE.saturating_sub(rebate.0) as u128 / SCALE as u128) as u64,
+        );
+
+        // A fee-free swap pays out exactly `staked * price`, computed in integers so the
+        // float fee path cannot introduce any error. It rounds like the regular path does.
+        if fee.0 == 0 {
+            let tokens_to_receive_scaled =
+                round_payout_scaled(staked_token_amount.0 as u128, self.price.0 as u128);
+            if tokens_to_receive_scaled > self.token_amount.0 {
+                return Err(Error::InsufficientLiquidity);
+            }
+            return Ok(SwapQuote {
+                tokens_to_receive_f64: tokens_to_receive_scaled as f64 / SCALE as f64,
+                tokens_to_receive_scaled,
+                fee,
+            });
+        }
+
+        let tokens_to_receive_f64 = staked_amount_f64 * price_f64 * (1.0 - fee.to_f64());
+
+        let tokens_to_receive_scaled = round_payout(tokens_to_receive_f64 * SCALE as f64);
+
+        // Check for available liquidity
+        if tokens_to_receive_scaled > self.token_amount.0 {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        Ok(SwapQuote {
+            tokens_to_receive_f64,
+            tokens_to_receive_scaled,
+            fee,
+        })
+    }
+
+    // Swap staked tokens. The price age limit is not checked; see `set_max_price_age`.
+    pub fn swap(&mut self, staked_token_amount: StakedTokenAmount) -> Result<f64, Error> {
+        self.ensure_not_paused()?;
+        let quote = self.quote_swap(staked_token_amount)?;
+        self.apply_swap(staked_token_amount, quote)
+    }
+
+    // Swap staked tokens on behalf of `address`, applying its configured fee rebate.
+    pub fn swap_for(
+        &mut self,
+        address: Address,
+        staked_token_amount: StakedTokenAmount,
+    ) -> Result<f64, Error> {
+        self.ensure_not_paused()?;
+        let rebate = self.fee_rebate(address);
+        let quote = self.quote_swap_with_rebate(staked_token_amount, rebate)?;
+        self.apply_swap(staked_token_amount, quote)
+    }
+
+    // Share of the swap fee refunded to `address`, 0 unless configured with `set_fee_rebate`.
+    fn fee_rebate(&self, address: Address) -> Percentage {
+        self.fee_rebates
+            .get(&address)
+            .copied()
+            .unwrap_or(Percentage(0))
+    }
+
+    // Apply a quoted swap to the pool state and return the tokens paid out.
+    fn apply_swap(
+        &mut self,
+        staked_token_amount: StakedTokenAmount,
+        quote: SwapQuote,
+    ) -> Result<f64, Error> {
+        // Checked arithmetic keeps rounding at the exact-liquidity boundary from ever panicking
+        let token_amount = self
+            .token_amount
+            .0
+            .checked_sub(quote.tokens_to_receive_scaled)
+            .ok_or(Error::InsufficientLiquidity)?;
+        let st_token_amount = self
+            .st_token_amount
+            .0
+            .checked_add(staked_token_amount.0)
+            .ok_or(Error::Overflow)?;
+
+        // Update state
+        self.token_amount.0 = token_amount;
+        self.st_token_amount.0 = st_token_amount;
+        self.record_backing_reference();
+        self.operation_count += 1;
+        self.stats.swap_count += 1;
+        self.stats.staked_volume.0 = self
+            .stats
+            .staked_volume
+            .0
+            .saturating_add(staked_token_amount.0);
+        self.stats.tokens_paid.0 = self
+            .stats
+            .tokens_paid
+            .0
+            .saturating_add(quote.tokens_to_receive_scaled);
+        let acquisition_value = self.value_in_tokens(staked_token_amount).0;
+        let fee_tokens = acquisition_value.saturating_sub(quote.tokens_to_receive_scaled);
+        self.last_op_pnl =
+            (acquisition_value as f64 - quote.tokens_to_receive_scaled as f64) / SCALE as f64;
+        self.stats.fees_collected.0 = self.stats.fees_collected.0.saturating_add(fee_tokens);
+        self.staked_cost_basis.0 = self
+            .staked_cost_basis
+            .0
+            .saturating_add(quote.tokens_to_receive_scaled);
+        self.staked_acquisition_value.0 = self
+            .staked_acquisition_value
+            .0
+            .saturating_add(acquisition_value);
+
+        if let Some(metrics_sink) = &self.metrics_sink {
+            metrics_sink.record_swap(staked_token_amount, quote.fee);
+        }
+
+        // Scale down the result to return the "natural" value
+        Ok(quote.tokens_to_receive_f64)
+    }
+
+    // Quote both directions in one read-only call: the tokens paid out for `staked_in`, and the
+    // staked tokens bought with `token_in`. Buying staked tokens costs the price plus the fee at
+    // the token balance after the trade, as in `marginal_prices`, and is limited to
+    // `available_staked`.
+    pub fn quote_both(
+        &self,
+        staked_in: StakedTokenAmount,
+        token_in: TokenAmount,
+    ) -> Result<(f64, f64), Error> {
+        let tokens_out = self.quote_swap(staked_in)?.tokens_to_receive_f64;
+        if token_in.0 == 0 || self.price.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+
+        let fee = self.fee_at_balance(TokenAmount(self.token_amount.0.saturating_add(token_in.0)));
+        let token_in_f64 = token_in.0 as f64 / SCALE as f64;
+        let price_f64 = self.price.0 as f64 / SCALE as f64;
+        let staked_out = token_in_f64 / (price_f64 * (1.0 + fee.to_f64()));
+        if staked_out * SCALE as f64 > self.available_staked().0 as f64 {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        Ok((tokens_out, staked_out))
+    }
+
+    // Share of a swap's fee-free value, `staked * price`, lost to the fee and rounding.
+    pub fn price_impact(
+        &self,
+        staked_token_amount: StakedTokenAmount,
+    ) -> Result<Percentage, Error> {
+        let quote = self.quote_swap(staked_token_amount)?;
+        Ok(self.impact_of(staked_token_amount, &quote))
+    }
+
+    // Staked tokens needed to receive exactly `tokens`, pricing the fee curve continuously as
+    // the balance falls (see `fee_integral`) rather than charging one flat fee for the whole
+    // swap. Rounded up so the output is never short.
+    pub fn quote_exact_out(&self, tokens: TokenAmount) -> Result<StakedTokenAmount, Error> {
+        if tokens.0 == 0 || self.price.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+        if tokens.0 > self.token_amount.0 {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        let balance_end = TokenAmount(self.token_amount.0 - tokens.0);
+        let fees = self.fee_integral(self.token_amount, balance_end);
+        if !fees.is_finite() {
+            return Err(Error::InsufficientLiquidity);
+        }
+
+        let gross_tokens = tokens.0 as f64 / SCALE as f64 + fees;
+        let price_f64 = self.price.0 as f64 / SCALE as f64;
+        StakedTokenAmount::try_from_f64(
+            (gross_tokens / price_f64 * SCALE as f64).ceil() / SCALE as f64,
+        )
+    }
+
+    fn impact_of(&self, staked_token_amount: StakedTokenAmount, quote: &SwapQuote) -> Percentage {
+        let gross_tokens = self.value_in_tokens(staked_token_amount).0 as u128;
+        if gross_tokens == 0 {
+            return Percentage(0);
+        }
+
+        let shortfall = gross_tokens.saturating_sub(quote.tokens_to_receive_scaled as u128);
+        Percentage(
+            mul_div(shortfall, SCALE as u128, gross_tokens).map_or(SCALE, |impact| impact as u64),
+        )
+    }
+
+    // Swap staked tokens, failing with `Error::ExcessiveImpact` and leaving the pool untouched
+    // if the swap's price impact exceeds `max_impact`.
+    pub fn swap_max_impact(
+        &mut self,
+        staked_token_amount: StakedTokenAmount,
+        max_impact: Percentage,
+    ) -> Result<f64, Error> {
+        self.ensure_not_paused()?;
+        let quote = self.quote_swap(staked_token_amount)?;
+        if self.impact_of(staked_token_amount, &quote).0 > max_impact.0 {
+            return Err(Error::ExcessiveImpact);
+        }
+        self.apply_swap(staked_token_amount, quote)
+    }
+
+    // Whether the pool's total value, in tokens at the current price, would still be at least
+    // `min_value` after swapping `amount`. The swap is simulated on a copy of the pool.
+    pub fn swap_keeps_solvent(
+        &self,
+        amount: StakedTokenAmount,
+        min_value: TokenAmount,
+    ) -> Result<bool, Error> {
+        let mut simulated_pool = self.hypothetical();
+        simulated_pool.swap(amount)?;
+
+        let value_after = simulated_pool.token_amount.0 as u128
+            + simulated_pool
+                .value_in_tokens(simulated_pool.st_token_amount)
+                .0 as u128;
+        Ok(value_after >= min_value.0 as u128)
+    }
+
+    // Lowest flat fee at which swapping `amount` does not lower the LP token price, found by
+    // simulating the swap on copies of the pool. The swap trades at the oracle price, so any fee
+    // enriches LPs and only payout rounding can push this above zero.
+    pub fn lp_neutral_fee(&self, amount: StakedTokenAmount) -> Result<Percentage, Error> {
+        let value_before = self.total_liabilities();
+        let value_after = |fee: u64| {
+            let mut simulated_pool = LpPool {
+                min_fee: Percentage(fee),
+                max_fee: Percentage(fee),
+                fee_strategy: Arc::new(LinearFeeStrategy),
+                ..self.hypothetical()
+            };
+            simulated_pool
+                .swap(amount)
+                .map(|_| simulated_pool.total_liabilities())
+        };
+        // A 100% fee pays nothing out, so it is LP-neutral whenever the swap is possible at all
+        value_after(SCALE)?;
+
+        // Binary search for the lowest fee that keeps the pool's value
+        let (mut low, mut high) = (0, SCALE);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match value_after(mid) {
+                Ok(value) if value.0 >= value_before.0 => high = mid,
+                _ => low = mid + 1,
+            }
+        }
+
+        Ok(Percentage(low))
+    }
+
+    // Swap as much of `staked_token_amount` as the pool's liquidity allows.
+    // Returns the tokens received and the staked amount actually consumed, which may be less than requested.
+    pub fn swap_clamped(
+        &mut self,
+        staked_token_amount: StakedTokenAmount,
+    ) -> Result<(f64, StakedTokenAmount), Error> {
+        self.ensure_not_paused()?;
+        if staked_token_amount.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+
+        // Binary search for the largest staked amount the pool can still pay out for
+        let mut consumed = staked_token_amount.0;
+        if self.quote_swap(staked_token_amount).is_err() {
+            let (mut low, mut high) = (0, staked_token_amount.0);
+            while low < high {
+                let mid = low + (high - low).div_ceil(2);
+                if self.quote_swap(StakedTokenAmount(mid)).is_ok() {
+                    low = mid;
+                } else {
+                    high = mid - 1;
+                }
+            }
+            if low == 0 {
+                return Err(Error::InsufficientLiquidity);
+            }
+            consumed = low;
+        }
+
+        let tokens_received = self.swap(StakedTokenAmount(consumed))?;
+        Ok((tokens_received, StakedTokenAmount(consumed)))
+    }
+
+    // Preview the output of a swap under an alternative fee band, leaving the pool's fees unchanged.
+    pub fn preview_with_fees(
+        &self,
+        staked_token_amount: StakedTokenAmount,
+        min_fee: Percentage,
+        max_fee: Percentage,
+    ) -> Result<f64, Error> {
+        if min_fee.0 > SCALE || max_fee.0 > SCALE {
+            return Err(Error::InvalidInput);
+        }
+
+        let preview_pool = LpPool {
+            min_fee,
+            max_fee,
+            ..self.hypothetical()
+        };
+        preview_pool
+            .quote_swap(staked_token_amount)
+            .map(|quote| quote.tokens_to_receive_f64)
+    }
+
+    // Change in the value LPs hold, in tokens, if `stress_volume` were swapped with the maximum
+    // fee set to `new_max_fee` instead of the current one. Both swaps are simulated on copies of
+    // the pool; a negative result is the LPs' loss from the change.
+    pub fn lp_loss_from_fee_change(
+        &self,
+        new_max_fee: Percentage,
+        stress_volume: StakedTokenAmount,
+    ) -> Result<f64, Error> {
+        if new_max_fee.0 > SCALE {
+            return Err(Error::InvalidInput);
+        }
+
+        let value_after = |simulated_pool: &mut LpPool| {
+            simulated_pool
+                .swap(stress_volume)
+                .map(|_| simulated_pool.total_liabilities().0 as f64)
+        };
+        let value_current = value_after(&mut self.hypothetical())?;
+        let value_new = value_after(&mut LpPool {
+            max_fee: new_max_fee,
+            ..self.hypothetical()
+        })?;
+
+        Ok((value_new - value_current) / SCALE as f64)
+    }
+
+    // Fee rate a swap of `staked_token_amount` would pay at the current state.
+    pub fn fee_for_amount(
+        &self,
+        staked_token_amount: StakedTokenAmount,
+    ) -> Result<Percentage, Error> {
+        self.quote_swap(staked_token_amount).map(|quote| quote.fee)
+    }
+
+    // Fee a swap of `staked_token_amount` would pay, in tokens: the gross value of the staked
+    // input at the current price minus the tokens actually paid out.
+    pub fn fee_amount(&self, staked_token_amount: StakedTokenAmount) -> Result<TokenAmount, Error> {
+        self.fee_quote(staked_token_amount)
+            .map(|fee_quote| fee_quote.amount)
+    }
+
+    // Fee a swap of `staked_token_amount` would pay, both as a rate and in tokens.
+    pub fn fee_quote(&self, staked_token_amount: StakedTokenAmount) -> Result<FeeQuote, Error> {
+        let quote = self.quote_swap(staked_token_amount)?;
+        let gross_tokens = self.value_in_tokens(staked_token_amount);
+
+        Ok(FeeQuote {
+            rate: quote.fee,
+            amount: TokenAmount(
+                gross_tokens
+                    .0
+                    .saturating_sub(quote.tokens_to_receive_scaled),
+            ),
+        })
+    }
+
+    // Derivative of the fee a swap of `volume` would pay, in tokens, with respect to `max_fee`
+    // as a fraction. Estimated by a central difference of 0.1% either way, on copies of the
+    // pool with the maximum fee moved, clamped to valid fees.
+    pub fn fee_elasticity(&self, volume: StakedTokenAmount) -> Result<f64, Error> {
+        let step = SCALE / 1000;
+        let fee_revenue = |max_fee: u64| {
+            let simulated_pool = LpPool {
+                max_fee: Percentage(max_fee),
+                ..self.hypothetical()
+            };
+            simulated_pool
+                .fee_quote(volume)
+                .map(|fee_quote| fee_quote.amount.0 as f64 / SCALE as f64)
+        };
+
+        let lower = self.max_fee.0.saturating_sub(step);
+        let upper = (self.max_fee.0 + step).min(SCALE);
+        let revenue_change = fee_revenue(upper)? - fee_revenue(lower)?;
+        Ok(revenue_change / Percentage(upper - lower).to_f64())
+    }
+
+    // Fee revenue, in tokens, a swap of `volume` would earn under each built-in fee curve,
+    // simulated on copies of the pool with its fee strategy replaced.
+    pub fn compare_fee_curves(
+        &self,
+        volume: StakedTokenAmount,
+    ) -> Result<Vec<(FeeCurve, f64)>, Error> {
+        FeeCurve::ALL
+            .iter()
+            .map(|&curve| {
+                let simulated_pool = LpPool {
+                    fee_strategy: curve.strategy(),
+                    ..self.hypothetical()
+                };
+                simulated_pool
+                    .fee_quote(volume)
+                    .map(|fee_quote| (curve, fee_quote.amount.0 as f64 / SCALE as f64))
+            })
+            .collect()
+    }
+
+    // Best- and worst-case outputs for a swap: `(min_fee_output, max_fee_output)`.
+    // A plain `swap` under the default `LinearFeeStrategy` always pays out within this range.
+    // Fee waivers and rebates lower the fee below `min_fee`, up to paying out the full value,
+    // and a custom `FeeStrategy` may charge outside `[min_fee, max_fee]`, so those swaps can
+    // fall outside it. Fails with the same error as `swap` for swaps the pool cannot service.
+    pub fn output_range(
+        &self,
+        staked_token_amount: StakedTokenAmount,
+    ) -> Result<(f64, f64), Error> {
+        self.quote_swap(staked_token_amount)?;
+
+        let staked_amount_f64 = staked_token_amount.0 as f64 / SCALE as f64;
+        let price_f64 = self.price.0 as f64 / SCALE as f64;
+        let gross_tokens = staked_amount_f64 * price_f64;
+
+        Ok((
+            gross_tokens * (1.0 - self.min_fee.to_f64()),
+            gross_tokens * (1.0 - self.max_fee.to_f64()),
+        ))
+    }
+
+    // Number of consecutive swaps of `staked_token_amount` the pool can service before running out
+    // of liquidity, simulated on a copy so the rising fee is accounted for. A swap that would pay out
+    // nothing (e.g. at a 100% fee) also counts as exhausting the pool. Each swap is simulated in
+    // turn, so swaps tiny relative to the pool fail with `Error::SimulationLimitExceeded` once
+    // `MAX_SIMULATED_SWAPS` have been counted rather than running for an unbounded time.
+    pub fn swaps_until_exhausted(
+        &self,
+        staked_token_amount: StakedTokenAmount,
+    ) -> Result<u64, Error> {
+        if staked_token_amount.0 == 0 {
+            return Err(Error::InvalidInput);
+        }
+
+        let mut simulated_pool = self.hypothetical();
+        let mut swaps = 0;
+        while let Ok(quote) = simulated_pool.quote_swap(staked_token_amount) {
+            if quote.tokens_to_receive_scaled == 0 {
+                break;
+            }
+            if swaps == MAX_SIMULATED_SWAPS {
+                return Err(Error::SimulationLimitExceeded);
+            }
+            simulated_pool.swap(staked_token_amount)?;
+            swaps += 1;
+        }
+
+        Ok(swaps)
+    }
+
+    // Smallest token balance that can service a swap of `staked_token_amount`. Seeded with
+    // exactly this balance, the swap drains the pool, so the fee at a zero balance is what
+    // applies: the maximum fee on the linear curve with a positive target, the minimum fee with
+    // a zero target, or whatever the fee strategy charges there. Any less and the payout
+    // exceeds the balance. An empty pool cannot swap at all, so a non-zero swap always needs
+    // at least one scaled unit.
+    pub fn min_balance_for_swap(&self, staked_token_amount: StakedTokenAmount) -> TokenAmount {
+        if staked_token_amount.0 == 0 {
+            return TokenAmount(0);
+        }
+
+        // Mirrors the payout rounding in `quote_swap_with_rebate`, including its fee-free path
+        let fee = self.fee_at_balance(TokenAmount(0));
+        let payout = if fee.0 == 0 {
+            round_payout_scaled(staked_token_amount.0 as u128, self.price.0 as u128)
+        } else {
+            let staked_amount_f64 = staked_token_amount.0 as f64 / SCALE as f64;
+            let price_f64 = self.price.0 as f64 / SCALE as f64;
+            let tokens_to_receive_f64 = staked_amount_f64 * price_f64 * (1.0 - fee.to_f64());
+            round_payout(tokens_to_receive_f64 * SCALE as f64)
+        };
+
+        TokenAmount(payout.max(1))
+    }
+
+    // Swap staked tokens at time `now`, rejecting the swap if the price is older than the configured maximum age.
+    // No fee is charged while a fee waiver is in effect.
+    pub fn swap_at(
+        &mut self,
+        staked_token_amount: StakedTokenAmount,
+        now: u64,
+    ) -> Result<f64, Error> {
+        let tokens_received = self.swap_with_rebate_at(staked_token_amount, Percentage(0), now)?;
+        self.record_operation(now, None, Operation::Swap(staked_token_amount));
+
+        Ok(tokens_received)
+    }
+
+    // Swap staked tokens on behalf of `address` at time `now`, like `swap_at`, recording the
+    // address as the actor.
+    pub fn swap_for_at(
+        &mut self,
+        address: Address,
+        staked_token_amount: StakedTokenAmount,
+        now: u64,
+    ) -> Result<f64, Error> {
+        let rebate = self.fee_rebate(address);
+        let tokens_received = self.swap_with_rebate_at(staked_token_amount, rebate, now)?;
+        self.record_operation(now, Some(address), Operation::Swap(staked_token_amount));
+
+        Ok(tokens_received)
+    }
+
+    // Apply a swap at time `now` with its fee reduced by `rebate`, or waived entirely while a
+    // fee waiver is in effect.
+    fn swap_with_rebate_at(
+        &mut self,
+        staked_token_amount: StakedTokenAmount,
+        rebate: Percentage,
+        now: u64,
+    ) -> Result<f64, Error> {
+        self.ensure_price_fresh(now)?;
+        self.ensure_not_paused()?;
+        let rebate = if self.fee_waiver_until.is_some_and(|until| now < until) {
+            Percentage(SCALE)
+        } else {
+            rebate
+        };
+        let quote = self.quote_swap_with_rebate(staked_token_amount, rebate)?;
+        self.apply_swap(staked_token_amount, quote)
+    }
+
+    // Add liquidity on behalf of `depositor` at time `now`, recording it in the operation log.
+    pub fn add_liquidity_for_at(
+        &mut self,
+        depositor: Address,
+        token_amount: TokenAmount,
+        now: u64,
+    ) -> Result<f64, Error> {
+        let lp_tokens_minted = self.add_liquidity_for(depositor, token_amount)?;
+        self.record_operation(now, Some(depositor), Operation::AddLiquidity(token_amount));
+
+        Ok(lp_tokens_minted)
+    }
+
+    // Remove liquidity on behalf of `depositor` at time `now`, recording it in the operation log.
+    pub fn remove_liquidity_for_at(
+        &mut self,
+        depositor: Address,
+        lp_token_amount: LpTokenAmount,
+        now: u64,
+    ) -> Result<(f64, f64), Error> {
+        let result = self.remove_liquidity_for(depositor, lp_token_amount)?;
+        self.record_operation(
+            now,
+            Some(depositor),
+            Operation::RemoveLiquidity(lp_token_amount),
+        );
+
+        Ok(result)
+    }
+
+    // Reject swaps at `now` if the price is older than the configured maximum age.
+    fn ensure_price_fresh(&self, now: u64) -> Result<(), Error> {
+        if let Some(max_price_age) = self.max_price_age {
+            if self.is_price_stale(now, max_price_age) {
+                return Err(Error::StalePrice);
+            }
+        }
+        Ok(())
+    }
+
+    fn record_operation(&mut self, timestamp: u64, actor: Option<Address>, operation: Operation) {
+        self.operation_log.push(OperationRecord {
+            timestamp,
+            operation,
+            actor,
+        });
+    }
+
+    // Indices into `operation_log` of swaps that received just-in-time liquidity: another
+    // actor deposited immediately before the swap and withdrew immediately after, collecting a
+    // share of its fee. The extra liquidity lowers the swapper's fee, so this is not an attack
+    // on the swapper; it dilutes the fees earned by the pool's standing LPs.
+    pub fn detect_jit_liquidity(&self) -> Vec<usize> {
+        self.operation_log
+            .windows(3)
+            .enumerate()
+            .filter(|(_, records)| {
+                let [deposit, swap, withdrawal] = records else {
+                    return false;
+                };
+                matches!(deposit.operation, Operation::AddLiquidity(_))
+                    && matches!(swap.operation, Operation::Swap(_))
+                    && matches!(withdrawal.operation, Operation::RemoveLiquidity(_))
+                    && deposit.actor.is_some()
+                    && deposit.actor == withdrawal.actor
+                    && swap.actor != deposit.actor
+            })
+            .map(|(index, _)| index + 1)
+            .collect()
+    }
+
+    // Operations recorded with a timestamp, oldest first.
+    pub fn operation_log(&self) -> &[OperationRecord] {
+        &self.operation_log
+    }
+
+    // Relative cost of applying `operation`, for budgeting batches. Deposits update one balance
+    // and mint, withdrawals pay out both balances, and swaps also evaluate the fee curve.
+    pub fn estimated_cost(operation: &Operation) -> u64 {
+        match operation {
+            Operation::AddLiquidity(_) => 2,
+            Operation::RemoveLiquidity(_) => 3,
+            Operation::Swap(_) => 4,
+        }
+    }
+
+    // Staked token volume swapped through `swap_at` or `swap_for_at` within the `window_secs` seconds up to `now`.
+    pub fn rolling_volume(&self, now: u64, window_secs: u64) -> StakedTokenAmount {
+        let window_start = now.saturating_sub(window_secs);
+        let volume = self
+            .operation_log
+            .iter()
+            .filter(|record| window_start < record.timestamp && record.timestamp <= now)
+            .map(|record| match record.operation {
+                Operation::Swap(staked_token_amount) => staked_token_amount.0,
+                _ => 0,
+            })
+            .sum();
+
+        StakedTokenAmount(volume)
+    }
+
+    // Swap staked tokens at most once per idempotency key.
+    // A repeated key returns the cached result of the first swap without touching the pool state.
+    // Only the most recent `IDEMPOTENCY_KEY_CAPACITY` keys are remembered.
+    pub fn swap_idempotent(
+        &mut self,
+        staked_token_amount: StakedTokenAmount,
+        key: u64,
+    ) -> Result<f64, Error> {
+        if let Some(&(_, cached_result)) = self
+            .applied_swap_keys
+            .iter()
+            .find(|(applied_key, _)| *applied_key == key)
+        {
+            return Ok(cached_result);
+        }
+
+        let result = self.swap(staked_token_amount)?;
+
+        if self.applied_swap_keys.len() == IDEMPOTENCY_KEY_CAPACITY {
+            self.applied_swap_keys.pop_front();
+        }
+        self.applied_swap_keys.push_back((key, result));
+
+        Ok(result)
+    }
+
+    // Stable 64-bit FNV-1a hash of the pool's balances, LP supply and price, for detecting
+    // unintended state changes. Balances are hashed as u128 like in `to_bytes`, so the hash does
+    // not depend on the `Balance` backend, the Rust version or the platform.
+    pub fn state_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut hash = FNV_OFFSET_BASIS;
+        for value in [
+            self.token_amount.0 as u128,
+            self.st_token_amount.0 as u128,
+            self.lp_token_amount.0 as u128,
+            self.price.0 as u128,
+        ] {
+            for byte in value.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        }
+        hash
+    }
+
+    // Serialize the pool state and configuration into a versioned little-endian byte layout.
+    // Balances are always written as u128 so snapshots load under either `Balance` backend.
+    // The idempotency key cache, operation log, stats, last operation PnL, metrics sink and fee
+    // strategy are runtime-only and are not persisted; a restored pool uses `LinearFeeStrategy`.
+    // Per-address maps are sorted by address. `from_bytes` also reads every earlier layout.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![SERIALIZATION_VERSION];
+        bytes.extend_from_slice(&self.price.0.to_le_bytes());
+        for balance in [
+            self.token_amount.0,
+            self.st_token_amount.0,
+            self.lp_token_amount.0,
+            self.liquidity_target.0,
+        ] {
+            bytes.extend_from_slice(&(balance as u128).to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.min_fee.0.to_le_bytes());
+        bytes.extend_from_slice(&self.max_fee.0.to_le_bytes());
+        bytes.push(self.min_deposit.is_some() as u8);
+        bytes.extend_from_slice(
+            &(self.min_deposit.map_or(0, |amount| amount.0) as u128).to_le_bytes(),
+        );
+        bytes.extend_from_slice(&(self.staked_reserve.0 as u128).to_le_bytes());
+        bytes.extend_from_slice(&self.created_at.to_le_bytes());
+        bytes.extend_from_slice(&self.operation_count.to_le_bytes());
+        bytes.extend_from_slice(&self.price_updated_at.to_le_bytes());
+        bytes.push(self.max_price_age.is_some() as u8);
+        bytes.extend_from_slice(&self.max_price_age.unwrap_or(0).to_le_bytes());
+        bytes.push(self.fee_waiver_until.is_some() as u8);
+        bytes.extend_from_slice(&self.fee_waiver_until.unwrap_or(0).to_le_bytes());
+        let mut lp_holdings: Vec<_> = self.lp_holdings.iter().collect();
+        lp_holdings.sort_by_key(|(depositor, _)| **depositor);
+        bytes.extend_from_slice(&(lp_holdings.len() as u64).to_le_bytes());
+        for (depositor, holding) in lp_holdings {
+            bytes.extend_from_slice(&depositor.0.to_le_bytes());
+            bytes.extend_from_slice(&(holding.0 as u128).to_le_bytes());
+        }
+        bytes.push(self.paused as u8);
+        bytes.push(self.max_token_amount.is_some() as u8);
+        bytes.extend_from_slice(
+            &(self.max_token_amount.map_or(0, |amount| amount.0) as u128).to_le_bytes(),
+        );
+        let mut fee_rebates: Vec<_> = self.fee_rebates.iter().collect();
+        fee_rebates.sort_by_key(|(address, _)| **address);
+        bytes.extend_from_slice(&(fee_rebates.len() as u64).to_le_bytes());
+        for (address, rebate) in fee_rebates {
+            bytes.extend_from_slice(&address.0.to_le_bytes());
+            bytes.extend_from_slice(&rebate.0.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.staked_cost_basis.0 as u128).to_le_bytes());
+        bytes.extend_from_slice(&(self.staked_acquisition_value.0 as u128).to_le_bytes());
+        bytes.extend_from_slice(&(self.net_deposits.0 as u128).to_le_bytes());
+        let (reference_tokens, reference_staked, reference_lp) = self.backing_reference;
+        for balance in [reference_tokens.0, reference_staked.0, reference_lp.0] {
+            bytes.extend_from_slice(&(balance as u128).to_le_bytes());
+        }
+        bytes.push(self.liquidity_target_fraction.is_some() as u8);
+        bytes.extend_from_slice(
+            &self
+                .liquidity_target_fraction
+                .map_or(0, |fraction| fraction.0)
+                .to_le_bytes(),
+        );
+        bytes
+    }
+
+    // Pack the pool's balances and parameters into a fixed layout of big-endian u64s, for
+    // deterministic comparison with on-chain state:
+    //   0..8 price, 8..16 token_amount, 16..24 st_token_amount, 24..32 lp_token_amount,
+    //   32..40 liquidity_target, 40..48 min_fee, 48..56 max_fee.
+    // Everything else is left out. Fails with `Error::Overflow` if a balance exceeds `u64::MAX`.
+    pub fn to_fixed_bytes(&self) -> Result<[u8; FIXED_BYTES_LEN], Error> {
+        let to_u64 = |balance: Balance| u64::try_from(balance as u128).map_err(|_| Error::Overflow);
+        let fields = [
+            self.price.0,
+            to_u64(self.token_amount.0)?,
+            to_u64(self.st_token_amount.0)?,
+            to_u64(self.lp_token_amount.0)?,
+            to_u64(self.liquidity_target.0)?,
+            self.min_fee.0,
+            self.max_fee.0,
+        ];
+
+        let mut bytes = [0; FIXED_BYTES_LEN];
+        for (chunk, field) in bytes.chunks_exact_mut(8).zip(fields) {
+            chunk.copy_from_slice(&field.to_be_bytes());
+        }
+        Ok(bytes)
+    }
+
+    // Restore a pool written by `to_fixed_bytes`, with every other setting at its default.
+    pub fn from_fixed_bytes(bytes: &[u8; FIXED_BYTES_LEN]) -> Result<Self, Error> {
+        let mut fields = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_be_bytes(chunk.try_into().expect("chunks are 8 bytes")));
+        let mut next = || fields.next().expect("the layout has seven fields");
+        let price = Price(next());
+        let token_amount = TokenAmount(next() as Balance);
+        let st_token_amount = StakedTokenAmount(next() as Balance);
+        let lp_token_amount = LpTokenAmount(next() as Balance);
+        let liquidity_target = TokenAmount(next() as Balance);
+
+        let mut lp_pool = LpPool::init(
+            price,
+            Percentage(next()),
+            Percentage(next()),
+            liquidity_target,
+        )?;
+        lp_pool.token_amount = token_amount;
+        lp_pool.st_token_amount = st_token_amount;
+        lp_pool.lp_token_amount = lp_token_amount;
+        lp_pool.record_backing_reference();
+        Ok(lp_pool)
+    }
+
+    // Restore a pool written by `to_bytes` under the current or any earlier layout version.
+    // Fields a snapshot predates are filled in as if the pool had just been loaded: settings
+    // take their `init` defaults, the staked balance counts as acquired at the snapshot's
+    // price without a fee, the pool's value as deposited by LPs, and the balances as the
+    // backing reference. Snapshots from a newer layout are rejected with
+    // `Error::UnsupportedVersion`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (&version, payload) = bytes.split_first().ok_or(Error::InvalidInput)?;
+        if version == 0 || version > SERIALIZATION_VERSION {
+            return Err(Error::UnsupportedVersion);
+        }
+
+        let mut reader = ByteReader { bytes: payload };
+        let price = Price(reader.read_u64()?);
+        let token_amount = TokenAmount(reader.read_balance()?);
+        let st_token_amount = StakedTokenAmount(reader.read_balance()?);
+        let lp_token_amount = LpTokenAmount(reader.read_balance()?);
+        let liquidity_target = TokenAmount(reader.read_balance()?);
+        let min_fee = Percentage(reader.read_u64()?);
+        let max_fee = Percentage(reader.read_u64()?);
+        let has_min_deposit = reader.read_flag()?;
+        let min_deposit = TokenAmount(reader.read_balance()?);
+        let staked_reserve = StakedTokenAmount(reader.read_balance()?);
+        let created_at = reader.read_u64()?;
+        let operation_count = reader.read_u64()?;
+        let price_updated_at = reader.read_u64()?;
+        let has_max_price_age = reader.read_flag()?;
+        let max_price_age = reader.read_u64()?;
+        let (has_fee_waiver_until, fee_waiver_until) = if version >= 9 {
+            (reader.read_flag()?, reader.read_u64()?)
+        } else {
+            (false, 0)
+        };
+        let mut lp_holdings = HashMap::new();
+        if version >= 2 {
+            let lp_holdings_count = reader.read_u64()?;
+            for _ in 0..lp_holdings_count {
+                let depositor = Address(reader.read_u64()?);
+                lp_holdings.insert(depositor, LpTokenAmount(reader.read_balance()?));
+            }
+        }
+        let paused = version >= 4 && reader.read_flag()?;
+        let (has_max_token_amount, max_token_amount) = if version >= 5 {
+            (reader.read_flag()?, TokenAmount(reader.read_balance()?))
+        } else {
+            (false, TokenAmount(0))
+        };
+        let mut fee_rebates = HashMap::new();
+        if version >= 3 {
+            let fee_rebates_count = reader.read_u64()?;
+            for _ in 0..fee_rebates_count {
+                let address = Address(reader.read_u64()?);
+                fee_rebates.insert(address, Percentage(reader.read_u64()?));
+            }
+        }
+        let staked_cost_basis = if version >= 6 {
+            Some((
+                TokenAmount(reader.read_balance()?),
+                TokenAmount(reader.read_balance()?),
+            ))
+        } else {
+            None
+        };
+        let net_deposits = if version >= 7 {
+            Some(TokenAmount(reader.read_balance()?))
+        } else {
+            None
+        };
+        let backing_reference = if version >= 10 {
+            Some((
+                TokenAmount(reader.read_balance()?),
+                StakedTokenAmount(reader.read_balance()?),
+                LpTokenAmount(reader.read_balance()?),
+            ))
+        } else {
+            None
+        };
+        let (has_liquidity_target_fraction, liquidity_target_fraction) = if version >= 8 {
+            (reader.read_flag()?, Percentage(reader.read_u64()?))
+        } else {
+            (false, Percentage(0))
+        };
+        if !reader.bytes.is_empty() {
+            return Err(Error::InvalidInput);
+        }
+
+        let mut lp_pool = LpPool::init_at(price, min_fee, max_fee, liquidity_target, created_at)?;
+        lp_pool.token_amount = token_amount;
+        lp_pool.st_token_amount = st_token_amount;
+        lp_pool.lp_token_amount = lp_token_amount;
+        lp_pool.min_deposit = has_min_deposit.then_some(min_deposit);
+        lp_pool.staked_reserve = staked_reserve;
+        lp_pool.operation_count = operation_count;
+        lp_pool.price_updated_at = price_updated_at;
+        lp_pool.max_price_age = has_max_price_age.then_some(max_price_age);
+        lp_pool.fee_waiver_until = has_fee_waiver_until.then_some(fee_waiver_until);
+        lp_pool.lp_holdings = lp_holdings;
+        lp_pool.fee_rebates = fee_rebates;
+        lp_pool.paused = paused;
+        lp_pool.max_token_amount = has_max_token_amount.then_some(max_token_amount);
+        let staked_value = lp_pool.value_in_tokens(st_token_amount);
+        (lp_pool.staked_cost_basis, lp_pool.staked_acquisition_value) =
+            staked_cost_basis.unwrap_or((staked_value, staked_value));
+        lp_pool.net_deposits = net_deposits.unwrap_or_else(|| lp_pool.total_liabilities());
+        match backing_reference {
+            Some(backing_reference) => lp_pool.backing_reference = backing_reference,
+            None => lp_pool.record_backing_reference(),
+        }
+        lp_pool.liquidity_target_fraction =
+            has_liquidity_target_fraction.then_some(liquidity_target_fraction);
+        Ok(lp_pool)
+    }
+}
+
+//Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init() {
+        // Tests if the liquidity pool can be initialized successfully.
+        let lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        );
+        assert!(lp_pool.is_ok());
+    }
+
+    #[test]
+    fn test_init_fee_above_100_percent() {
+        // Tests that init rejects a fee above 100%.
+        let lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(9000000), // 900%
+            TokenAmount(90000000),
+        );
+        assert_eq!(lp_pool.unwrap_err(), Error::InvalidInput);
+    }
+
+    #[test]
+    fn test_add_liquidity() {
+        // Tests adding liquidity to the pool and expects it to succeed.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let result = lp_pool.add_liquidity(TokenAmount(100000000));
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 100.0); // Verify the amount of liquidity added matches expectation.
+    }
+
+    #[test]
+    fn test_remove_liquidity_insufficient() {
+        // Tests removing more liquidity than available and expects it to fail.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let result = lp_pool.remove_liquidity(LpTokenAmount(200000000)); // Attempt to remove more than available
+        assert!(result.is_err());
+        assert_eq!(result, Err(Error::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_remove_liquidity_valid() {
+        // Tests valid removal of liquidity and expects it to succeed.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let result = lp_pool.remove_liquidity(LpTokenAmount(100000000));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_fee_at_balance() {
+        // Tests that the fee curve falls linearly from max_fee at an empty pool to min_fee at the target.
+        let lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let balances = [0, 22500000, 45000000, 67500000, 90000000, 180000000];
+        let fees: Vec<u64> = balances
+            .iter()
+            .map(|balance| lp_pool.fee_at_balance(TokenAmount(*balance)).0)
+            .collect();
+        assert_eq!(fees, vec![900000, 697500, 495000, 292500, 90000, 90000]);
+        assert!(fees.windows(2).all(|pair| pair[0] >= pair[1])); // The fee never rises with the balance.
+    }
+
+    #[test]
+    fn test_swap_fee_from_post_swap_balance() {
+        // Tests that a swap pays the fee at the token balance it leaves behind.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+
+        // 9 tokens' worth leaves 91 tokens, above the target, so the minimum fee applies
+        let result = lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert!((result - 9.0 * 0.91).abs() < 1e-9);
+
+        // 45 tokens' worth out of the remaining 91.81 leaves 46.81, well below the target
+        let fee = lp_pool.fee_at_balance(TokenAmount(46810000)).to_f64();
+        let result = lp_pool.swap(StakedTokenAmount(30000000)).unwrap();
+        assert!((result - 45.0 * (1.0 - fee)).abs() < 1e-9);
+        assert!(fee > 0.09);
+    }
+
+    #[test]
+    fn test_swap_invalid_input() {
+        // Tests swapping with an invalid input amount (0) and expects it to fail.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let result = lp_pool.swap(StakedTokenAmount(0)); // Attempt to swap with an amount of 0
+        assert!(result.is_err());
+        assert_eq!(result, Err(Error::InvalidInput));
+    }
+
+    #[test]
+    fn test_swap_insufficient_liquidity() {
+        // Tests swapping with an amount that exceeds available liquidity and expects it to fail.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let result = lp_pool.swap(StakedTokenAmount(100000000000)); // Attempt to swap with a large amount exceeding liquidity
+        assert!(result.is_err());
+        assert_eq!(result, Err(Error::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_swap_valid() {
+        // Tests a valid swap operation and expects it to succeed.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let result = lp_pool.swap(StakedTokenAmount(50000000)); // Valid swap operation
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_swap_idempotent() {
+        // Tests that repeating an idempotency key returns the cached result and changes the pool only once.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let first = lp_pool
+            .swap_idempotent(StakedTokenAmount(6000000), 42)
+            .unwrap();
+        let token_amount_after_first = lp_pool.token_amount.0;
+        let st_token_amount_after_first = lp_pool.st_token_amount.0;
+
+        let second = lp_pool
+            .swap_idempotent(StakedTokenAmount(6000000), 42)
+            .unwrap(); // Retry with the same key
+        assert_eq!(first, second);
+        assert_eq!(lp_pool.token_amount.0, token_amount_after_first);
+        assert_eq!(lp_pool.st_token_amount.0, st_token_amount_after_first);
+        assert_eq!(lp_pool.st_token_amount.0, 6000000); // Only one swap was applied.
+    }
+
+    #[test]
+    fn test_fee_schedule() {
+        // Tests that the fee schedule reflects the fees passed at init.
+        let lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let fee_schedule = lp_pool.fee_schedule();
+        assert_eq!(fee_schedule.swap_min_fee, Percentage(90000));
+        assert_eq!(fee_schedule.swap_max_fee, Percentage(900000));
+    }
+
+    #[test]
+    fn test_at_price() {
+        // Tests that at_price values the pool at the new price without altering the original.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(10000000)).unwrap();
+        let original_value = lp_pool.total_value();
+
+        let stressed_pool = lp_pool.at_price(Price(750000)); // Staked token price halves
+        assert_eq!(lp_pool.price.0, 1500000);
+        assert_eq!(lp_pool.total_value(), original_value);
+        let token_amount_f64 = lp_pool.token_amount.0 as f64 / SCALE as f64;
+        assert!((stressed_pool.total_value() - (token_amount_f64 + 10.0 * 0.75)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_lp_fee_apr() {
+        // Tests that fee income over a quarter of a year is annualized relative to the pool value.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let apr = lp_pool.lp_fee_apr(1.0, SECONDS_PER_YEAR / 4); // 1 token earned on 100 in a quarter
+        assert!((apr - 0.04).abs() < 1e-12);
+        assert_eq!(lp_pool.lp_fee_apr(1.0, 0), 0.0);
+    }
+
+    #[test]
+    fn test_split() {
+        // Tests that splitting off 30% of the pool conserves every balance across both pools.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let (token_amount, st_token_amount, lp_token_amount) = (
+            lp_pool.token_amount.0,
+            lp_pool.st_token_amount.0,
+            lp_pool.lp_token_amount.0,
+        );
+
+        let new_pool = lp_pool.split(Percentage(300000)).unwrap();
+        assert_eq!(new_pool.st_token_amount.0, 1800000);
+        assert_eq!(new_pool.lp_token_amount.0, 30000000);
+        assert_eq!(
+            lp_pool.token_amount.0 + new_pool.token_amount.0,
+            token_amount
+        );
+        assert_eq!(
+            lp_pool.st_token_amount.0 + new_pool.st_token_amount.0,
+            st_token_amount
+        );
+        assert_eq!(
+            lp_pool.lp_token_amount.0 + new_pool.lp_token_amount.0,
+            lp_token_amount
+        );
+        assert_eq!(
+            lp_pool.split(Percentage(1000001)).unwrap_err(),
+            Error::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_split_keeps_configuration() {
+        // Tests that the split-off pool charges the same fees and enforces the same limits.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.set_fee_strategy(Arc::new(QuadraticFeeStrategy));
+        lp_pool.set_min_deposit(Some(TokenAmount(2000000)));
+        lp_pool.set_max_token_amount(Some(TokenAmount(200000000)));
+        lp_pool.set_staked_reserve(StakedTokenAmount(1000000));
+        lp_pool.set_max_price_age(Some(10));
+        lp_pool.set_fee_waiver_until(Some(5));
+        lp_pool
+            .set_fee_rebate(Address(1), Some(Percentage(500000)))
+            .unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+
+        let new_pool = lp_pool.split(Percentage(500000)).unwrap();
+        assert_eq!(new_pool.config(), lp_pool.config());
+        for balance in [0, 20000000, 45000000, 90000000] {
+            assert_eq!(
+                new_pool.fee_at_balance(TokenAmount(balance)),
+                lp_pool.fee_at_balance(TokenAmount(balance))
+            );
+        }
+        assert_eq!(new_pool.min_deposit, lp_pool.min_deposit);
+        assert_eq!(new_pool.max_token_amount, lp_pool.max_token_amount);
+        assert_eq!(new_pool.staked_reserve, lp_pool.staked_reserve);
+        assert_eq!(new_pool.max_price_age, lp_pool.max_price_age);
+        assert_eq!(new_pool.fee_waiver_until, lp_pool.fee_waiver_until);
+        assert_eq!(new_pool.price_updated_at, lp_pool.price_updated_at);
+        assert_eq!(new_pool.fee_rebate(Address(1)), Percentage(500000));
+        assert_eq!(new_pool.operation_count(), 0);
+        assert_eq!(new_pool.stats().swap_count, 0);
+    }
+
+    #[test]
+    fn test_split_lp_holdings() {
+        // Tests that tracked LP holdings are split pro rata and stay redeemable in both pools.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool
+            .add_liquidity_for(Address(1), TokenAmount(75000000))
+            .unwrap();
+        lp_pool
+            .add_liquidity_for(Address(2), TokenAmount(25000000))
+            .unwrap();
+        let concentration = lp_pool.lp_concentration();
+
+        let mut new_pool = lp_pool.split(Percentage(300000)).unwrap();
+        assert_eq!(lp_pool.lp_holding(Address(1)), LpTokenAmount(52500000));
+        assert_eq!(new_pool.lp_holding(Address(1)), LpTokenAmount(22500000));
+        assert_eq!(lp_pool.lp_holding(Address(2)), LpTokenAmount(17500000));
+        assert_eq!(new_pool.lp_holding(Address(2)), LpTokenAmount(7500000));
+        assert!((lp_pool.lp_concentration() - concentration).abs() < 1e-12);
+        assert!((new_pool.lp_concentration() - concentration).abs() < 1e-12);
+
+        lp_pool
+            .remove_liquidity_for(Address(1), LpTokenAmount(52500000))
+            .unwrap();
+        new_pool
+            .remove_liquidity_for(Address(2), LpTokenAmount(7500000))
+            .unwrap();
+        assert_eq!(new_pool.lp_holding(Address(2)), LpTokenAmount(0));
+
+        // Splitting off everything leaves no holdings behind
+        let rest = lp_pool.split(Percentage(1000000)).unwrap();
+        assert_eq!(lp_pool.lp_holding(Address(2)), LpTokenAmount(0));
+        assert_eq!(lp_pool.lp_concentration(), 0.0);
+        assert_eq!(rest.lp_holding(Address(2)), LpTokenAmount(17500000));
+    }
+
+    #[test]
+    fn test_add_liquidity_at_min_deposit() {
+        // Tests that a deposit exactly at the configured minimum is accepted.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.set_min_deposit(Some(TokenAmount(1000000)));
+        let result = lp_pool.add_liquidity(TokenAmount(1000000));
+        assert_eq!(result, Ok(1.0));
+    }
+
+    #[test]
+    fn test_add_liquidity_below_min_deposit() {
+        // Tests that a deposit below the configured minimum is rejected and leaves the pool untouched.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.set_min_deposit(Some(TokenAmount(1000000)));
+        let result = lp_pool.add_liquidity(TokenAmount(999999));
+        assert_eq!(result, Err(Error::BelowMinimum));
+        assert_eq!(lp_pool.token_amount.0, 0);
+    }
+
+    #[test]
+    fn test_fee_adjusted_price() {
+        // Tests that the fee-adjusted price is spot minus min_fee above the target and lower once fees are elevated.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let min_fee_price = 1.5 * (1.0 - 0.09);
+        assert!((lp_pool.fee_adjusted_price() - min_fee_price).abs() < 1e-12);
+
+        lp_pool.swap(StakedTokenAmount(20000000)).unwrap(); // Drain the pool below its liquidity target
+        assert!(lp_pool.fee_adjusted_price() < min_fee_price);
+    }
+
+    #[cfg(feature = "u128")]
+    #[test]
+    fn test_add_liquidity_beyond_u64() {
+        // Tests that the u128 backend handles balances beyond the u64 scaled cap.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let deposit = (1u128 << 50) * SCALE as u128; // ~1.1e21 scaled units, above u64::MAX
+        assert!(deposit > u64::MAX as u128);
+        let result = lp_pool.add_liquidity(TokenAmount(deposit));
+        assert_eq!(result, Ok((1u64 << 50) as f64));
+        assert_eq!(lp_pool.token_amount.0, deposit);
+        assert_eq!(lp_pool.lp_token_amount.0, deposit);
+        assert!(lp_pool.swap(StakedTokenAmount(deposit / 2)).is_ok());
+
+        // Payouts multiply two such balances, which overflows even a u128 intermediate
+        let (token_amount, st_token_amount) = (lp_pool.token_amount.0, lp_pool.st_token_amount.0);
+        let lp_supply = lp_pool.lp_token_amount.0;
+        assert!(token_amount.checked_mul(lp_supply / 2).is_none());
+        lp_pool
+            .remove_liquidity(LpTokenAmount(lp_supply / 2))
+            .unwrap();
+        assert_eq!(lp_pool.token_amount.0, token_amount - token_amount / 2);
+        assert_eq!(
+            lp_pool.st_token_amount.0,
+            st_token_amount - st_token_amount / 2
+        );
+        assert_eq!(lp_pool.lp_token_amount.0, lp_supply - lp_supply / 2);
+        assert_eq!(
+            lp_pool.lp_for_token_out(TokenAmount(lp_pool.token_amount.0)),
+            Ok(LpTokenAmount(lp_pool.lp_token_amount.0))
+        );
+        lp_pool
+            .remove_liquidity(LpTokenAmount(lp_pool.lp_token_amount.0))
+            .unwrap();
+        assert_eq!(lp_pool.token_amount.0, 0);
+        assert_eq!(lp_pool.st_token_amount.0, 0);
+    }
+
+    #[test]
+    fn test_mul_div() {
+        // Tests that products beyond a u128 are divided exactly, rounding down or up.
+        assert_eq!(mul_div(6, 7, 4), Some(10));
+        assert_eq!(mul_div_ceil(6, 7, 4), Some(11));
+        assert_eq!(mul_div_ceil(6, 8, 4), Some(12));
+        assert_eq!(mul_div(1, 1, 0), None);
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX), Some(u128::MAX));
+        assert_eq!(
+            mul_div(u128::MAX, u128::MAX - 1, u128::MAX),
+            Some(u128::MAX - 1)
+        );
+        assert_eq!(mul_div(u128::MAX, 3, 6), Some(u128::MAX / 2));
+        assert_eq!(mul_div(u128::MAX, 2, 1), None);
+        // (2^127 + 1) * 6 / 4 = 3 * 2^126 + 1.5
+        let big = (1u128 << 127) + 1;
+        assert_eq!(mul_div(big, 6, 4), Some(3 * (1u128 << 126) + 1));
+        assert_eq!(mul_div_ceil(big, 6, 4), Some(3 * (1u128 << 126) + 2));
+        assert_eq!(mul_div_ceil(u128::MAX, u128::MAX, u128::MAX - 1), None);
+    }
+
+    #[test]
+    fn test_available_staked() {
+        // Tests that the available staked balance tracks swaps and excludes the reserved floor.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.available_staked().0, 0);
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(4000000)).unwrap();
+        assert_eq!(lp_pool.available_staked().0, 10000000);
+
+        lp_pool.set_staked_reserve(StakedTokenAmount(3000000));
+        assert_eq!(lp_pool.available_staked().0, 7000000);
+        lp_pool.set_staked_reserve(StakedTokenAmount(20000000)); // Reserve above the balance
+        assert_eq!(lp_pool.available_staked().0, 0);
+    }
+
+    #[test]
+    fn test_swap_fees_reproducible() {
+        // Tests that two identically initialized pools charge identical fees across the same ten swaps.
+        let new_pool = || {
+            let mut lp_pool = LpPool::init(
+                Price(1500000),
+                Percentage(90000),
+                Percentage(900000),
+                TokenAmount(90000000),
+            )
+            .unwrap();
+            lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+            lp_pool
+        };
+        let mut first_pool = new_pool();
+        let mut second_pool = new_pool();
+        for _ in 0..10 {
+            let first_result = first_pool.swap(StakedTokenAmount(3000000));
+            let second_result = second_pool.swap(StakedTokenAmount(3000000));
+            assert_eq!(first_result, second_result);
+            assert_eq!(
+                first_pool.fee_at_balance(first_pool.token_amount),
+                second_pool.fee_at_balance(second_pool.token_amount)
+            );
+        }
+    }
+
+    #[test]
+    fn test_fee_breakeven_size() {
+        // Tests that a swap of the break-even size pushes the fee to the requested edge.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let size = lp_pool.fee_breakeven_size(Percentage(495000)).unwrap();
+        assert_eq!(size.0, 36666592); // ~55 tokens of headroom above the ~45 token balance at 49.5%
+
+        let balance_after = lp_pool.token_amount.0 - size.0 * 1500000 / SCALE as Balance;
+        let fee = lp_pool.fee_at_balance(TokenAmount(balance_after));
+        assert!(fee.0.abs_diff(495000) <= 1);
+        assert_eq!(
+            lp_pool.fee_breakeven_size(Percentage(950000)),
+            Err(Error::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_age_and_operation_count() {
+        // Tests that the operation count only grows on successful operations and age is measured from creation.
+        let mut lp_pool = LpPool::init_at(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+            1000,
+        )
+        .unwrap();
+        assert_eq!(lp_pool.age(1000), 0);
+        assert_eq!(lp_pool.age(4600), 3600);
+        assert_eq!(lp_pool.operation_count(), 0);
+
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let _ = lp_pool.swap(StakedTokenAmount(0)); // Rejected operations are not counted
+        lp_pool.remove_liquidity(LpTokenAmount(10000000)).unwrap();
+        assert_eq!(lp_pool.operation_count(), 3);
+    }
+
+    #[test]
+    fn test_preview_with_fees() {
+        // Tests that previewing under different fee bands changes the output but not the pool.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let current_band = lp_pool
+            .preview_with_fees(
+                StakedTokenAmount(6000000),
+                Percentage(90000),
+                Percentage(900000),
+            )
+            .unwrap();
+        let cheaper_band = lp_pool
+            .preview_with_fees(
+                StakedTokenAmount(6000000),
+                Percentage(1000),
+                Percentage(90000),
+            )
+            .unwrap();
+        assert!((cheaper_band - 8.991).abs() < 1e-9); // 9 tokens at the 0.1% minimum fee
+        assert!(cheaper_band > current_band);
+        assert_eq!(lp_pool.min_fee, Percentage(90000));
+        assert_eq!(lp_pool.swap(StakedTokenAmount(6000000)), Ok(current_band));
+    }
+
+    #[test]
+    fn test_swap_clamped() {
+        // Tests that an oversized swap is capped to the largest amount the pool can service.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let pool_before = lp_pool.clone();
+
+        let requested = StakedTokenAmount(1000000000);
+        let (tokens_received, consumed) = lp_pool.swap_clamped(requested).unwrap();
+        assert!(consumed.0 < requested.0);
+        assert_eq!(pool_before.clone().swap(consumed), Ok(tokens_received));
+        assert_eq!(
+            pool_before.clone().swap(StakedTokenAmount(consumed.0 + 1)),
+            Err(Error::InsufficientLiquidity)
+        );
+        assert_eq!(lp_pool.st_token_amount, consumed);
+    }
+
+    #[test]
+    fn test_value_in_tokens_and_staked() {
+        // Tests round-tripping values between staked tokens and tokens at a non-unit price.
+        let lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(
+            lp_pool.value_in_tokens(StakedTokenAmount(10000000)).0,
+            15000000
+        );
+        assert_eq!(
+            lp_pool.value_in_staked(TokenAmount(15000000)),
+            Ok(StakedTokenAmount(10000000))
+        );
+
+        let staked = StakedTokenAmount(7000001);
+        let round_trip = lp_pool
+            .value_in_staked(lp_pool.value_in_tokens(staked))
+            .unwrap();
+        assert!(staked.0 - round_trip.0 <= 1); // Rounding always truncates in the pool's favor
+    }
+
+    #[test]
+    fn test_price_staleness() {
+        // Tests that the price becomes stale after max_age and that only the timestamped swaps then reject swaps.
+        let mut lp_pool = LpPool::init_at(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+            1000,
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.set_max_price_age(Some(60));
+        assert!(!lp_pool.is_price_stale(1060, 60));
+        assert!(lp_pool.is_price_stale(1061, 60));
+        assert_eq!(
+            lp_pool.swap_at(StakedTokenAmount(6000000), 1061),
+            Err(Error::StalePrice)
+        );
+
+        // Swaps without a timestamp cannot tell the price's age, so they do not enforce the limit
+        assert!(lp_pool.swap(StakedTokenAmount(6000000)).is_ok());
+        assert!(lp_pool
+            .swap_for(Address(1), StakedTokenAmount(1000000))
+            .is_ok());
+        assert_eq!(
+            lp_pool.swap_for_at(Address(1), StakedTokenAmount(1000000), 1061),
+            Err(Error::StalePrice)
+        );
+
+        lp_pool.update_price(Price(1500000), 1061).unwrap();
+        assert!(!lp_pool.is_price_stale(1061, 60));
+        assert!(lp_pool.swap_at(StakedTokenAmount(6000000), 1061).is_ok());
+        assert_eq!(
+            lp_pool.update_price(Price(0), 1062),
+            Err(Error::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_dilution() {
+        // Tests that dilution is proportional to the deposit relative to the pool size.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.dilution(TokenAmount(100000000)), Ok(Percentage(0)));
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(
+            lp_pool.dilution(TokenAmount(25000000)),
+            Ok(Percentage(200000))
+        );
+        assert_eq!(
+            lp_pool.dilution(TokenAmount(100000000)),
+            Ok(Percentage(500000))
+        );
+        assert_eq!(lp_pool.dilution(TokenAmount(0)), Err(Error::InvalidInput));
+
+        // The dilution matches the share actually lost by the existing LP tokens
+        let existing_lp = lp_pool.lp_token_amount.0 as f64;
+        lp_pool.add_liquidity(TokenAmount(25000000)).unwrap();
+        assert!((existing_lp / lp_pool.lp_token_amount.0 as f64 - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_first_deposits_lp_ratio() {
+        // Tests that the first deposit anchors LP tokens 1:1 to tokens and later deposits keep the LP price stable.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.lp_token_price(), 1.0);
+        assert_eq!(lp_pool.add_liquidity(TokenAmount(100000000)), Ok(100.0));
+        assert_eq!(lp_pool.lp_token_price(), 1.0);
+
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap(); // Fees raise the value backing each LP token
+        let lp_price_before = lp_pool.lp_token_price();
+        assert!(lp_price_before > 1.0);
+        let minted = lp_pool.add_liquidity(TokenAmount(10000000)).unwrap();
+        assert!((minted - 10.0 / lp_price_before).abs() < 1e-9);
+        assert!((lp_pool.lp_token_price() - lp_price_before).abs() < 1e-6); // Minted LP tokens are truncated to whole scaled units
+    }
+
+    #[test]
+    fn test_rebalance_swap() {
+        // Tests that swapping the computed amount brings the pool composition to the target ratio.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.composition(), Percentage(1000000));
+
+        let target_ratio = Percentage(800000); // 80% tokens, 20% staked tokens by value
+        let staked_amount = lp_pool.rebalance_swap(target_ratio).unwrap();
+        lp_pool.swap(staked_amount).unwrap();
+        assert!(lp_pool.composition().0.abs_diff(target_ratio.0) <= 10);
+        assert_eq!(
+            lp_pool.rebalance_swap(Percentage(900000)),
+            Err(Error::InvalidInput)
+        );
+
+        // A 100% fee on an empty pool keeps any swap from emptying the token side
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(1000000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(
+            lp_pool.rebalance_swap(Percentage(0)),
+            Err(Error::InsufficientLiquidity)
+        );
+        let staked_amount = lp_pool.rebalance_swap(Percentage(100000)).unwrap();
+        lp_pool.swap(staked_amount).unwrap();
+        assert!(lp_pool.composition().0.abs_diff(100000) <= 10);
+    }
+
+    #[test]
+    fn test_state_hash() {
+        // Tests that the state hash changes with the state and matches after restoring a snapshot.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let snapshot = lp_pool.to_bytes();
+        let hash_before = lp_pool.state_hash();
+        assert_eq!(lp_pool.clone().state_hash(), hash_before);
+
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert_ne!(lp_pool.state_hash(), hash_before);
+        assert_ne!(
+            lp_pool.at_price(Price(1600000)).state_hash(),
+            lp_pool.state_hash()
+        );
+
+        let restored = LpPool::from_bytes(&snapshot).unwrap();
+        assert_eq!(restored.state_hash(), hash_before);
+    }
+
+    #[test]
+    fn test_serialization_round_trip() {
+        // Tests that a serialized pool restores with identical state and configuration.
+        let mut lp_pool = LpPool::init_at(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+            1000,
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        lp_pool.set_min_deposit(Some(TokenAmount(1000000)));
+        lp_pool.set_max_price_age(Some(60));
+
+        let bytes = lp_pool.to_bytes();
+        assert_eq!(bytes[0], SERIALIZATION_VERSION);
+        let restored = LpPool::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.to_bytes(), bytes);
+        assert_eq!(restored.token_amount, lp_pool.token_amount);
+        assert_eq!(restored.min_deposit, Some(TokenAmount(1000000)));
+        assert_eq!(restored.operation_count(), 2);
+    }
+
+    #[test]
+    fn test_deserialize_unsupported_version() {
+        // Tests that snapshots of another layout version or truncated input are rejected cleanly.
+        let lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let mut bytes = lp_pool.to_bytes();
+        assert_eq!(
+            LpPool::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+            Error::InvalidInput
+        );
+        bytes[0] = SERIALIZATION_VERSION + 1; // Snapshot written by a newer crate version
+        assert_eq!(
+            LpPool::from_bytes(&bytes).unwrap_err(),
+            Error::UnsupportedVersion
+        );
+        bytes[0] = 0;
+        assert_eq!(
+            LpPool::from_bytes(&bytes).unwrap_err(),
+            Error::UnsupportedVersion
+        );
+        assert_eq!(LpPool::from_bytes(&[]).unwrap_err(), Error::InvalidInput);
+    }
+
+    #[test]
+    fn test_deserialize_version_1() {
+        // Tests that a snapshot written by the first layout version still loads, with every
+        // field added since at its default.
+        // Written by version 1 from the standard pool after a 6 staked token swap, with a
+        // 1 token minimum deposit, a 60 second max price age and a 0.5 staked token reserve
+        let bytes = include_bytes!("../tests/fixtures/pool_v1.bin");
+        assert_eq!(bytes[0], 1);
+        let lp_pool = LpPool::from_bytes(bytes).unwrap();
+        assert_eq!(lp_pool.config().price, Price(1500000));
+        assert_eq!(lp_pool.token_amount, TokenAmount(91810000));
+        assert_eq!(lp_pool.st_token_amount, StakedTokenAmount(6000000));
+        assert_eq!(lp_pool.lp_token_amount, LpTokenAmount(100000000));
+        assert_eq!(lp_pool.min_deposit, Some(TokenAmount(1000000)));
+        assert_eq!(lp_pool.max_price_age, Some(60));
+        assert_eq!(lp_pool.staked_reserve, StakedTokenAmount(500000));
+        assert_eq!(lp_pool.created_at, 1000);
+        assert_eq!(lp_pool.operation_count(), 2);
+        assert!(lp_pool.lp_holdings.is_empty());
+        assert!(lp_pool.fee_rebates.is_empty());
+        assert!(!lp_pool.is_paused());
+        assert_eq!(lp_pool.max_token_amount, None);
+        assert_eq!(lp_pool.fee_waiver_until, None);
+        assert_eq!(lp_pool.liquidity_target_fraction, None);
+        assert_eq!(lp_pool.fee_split(), (0.0, 0.0));
+        assert_eq!(lp_pool.net_deposits, TokenAmount(100810000));
+        assert_eq!(
+            lp_pool.backing_reference,
+            (
+                lp_pool.token_amount,
+                lp_pool.st_token_amount,
+                lp_pool.lp_token_amount
+            )
+        );
+
+        // Saving upgrades the snapshot to the current layout without changing the pool
+        let upgraded = lp_pool.to_bytes();
+        assert_eq!(upgraded[0], SERIALIZATION_VERSION);
+        assert_eq!(LpPool::from_bytes(&upgraded).unwrap().to_bytes(), upgraded);
+        assert_eq!(
+            LpPool::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+            Error::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_marginal_prices() {
+        // Tests that the marginal prices straddle the spot price by the marginal fee.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(20000000)).unwrap();
+        let marginal_fee = lp_pool.fee_at_balance(lp_pool.token_amount).to_f64();
+
+        let (staked_to_token, token_to_staked) = lp_pool.marginal_prices();
+        assert!(staked_to_token < 1.5 && token_to_staked > 1.5);
+        assert!((1.5 - staked_to_token - 1.5 * marginal_fee).abs() < 1e-12);
+        assert!((token_to_staked - 1.5 - 1.5 * marginal_fee).abs() < 1e-12);
+        assert_eq!(staked_to_token, lp_pool.fee_adjusted_price());
+    }
+
+    #[test]
+    fn test_swap_empty_pool() {
+        // Tests that swapping into a freshly initialized pool fails cleanly, even with a zero liquidity target.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let result = lp_pool.swap(StakedTokenAmount(1000000));
+        assert_eq!(result, Err(Error::InsufficientLiquidity));
+        assert_eq!(lp_pool.st_token_amount.0, 0);
+
+        let mut untargeted_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(0),
+        )
+        .unwrap();
+        let result = untargeted_pool.swap(StakedTokenAmount(1000000));
+        assert_eq!(result, Err(Error::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_lp_concentration() {
+        // Tests the concentration of LP ownership between two depositors of known sizes.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool
+            .add_liquidity_for(Address(1), TokenAmount(75000000))
+            .unwrap();
+        lp_pool
+            .add_liquidity_for(Address(2), TokenAmount(25000000))
+            .unwrap();
+        assert!((lp_pool.lp_concentration() - (0.75f64.powi(2) + 0.25f64.powi(2))).abs() < 1e-12);
+
+        // A depositor cannot burn more than they hold, and a full exit leaves a single owner
+        assert_eq!(
+            lp_pool.remove_liquidity_for(Address(2), LpTokenAmount(25000001)),
+            Err(Error::InsufficientLiquidity)
+        );
+        lp_pool
+            .remove_liquidity_for(Address(2), LpTokenAmount(25000000))
+            .unwrap();
+        assert_eq!(lp_pool.lp_holding(Address(2)), LpTokenAmount(0));
+        assert!((lp_pool.lp_concentration() - 1.0).abs() < 1e-12);
+        assert_eq!(
+            LpPool::from_bytes(&lp_pool.to_bytes())
+                .unwrap()
+                .lp_holding(Address(1)),
+            LpTokenAmount(75000000)
+        );
+    }
+
+    #[test]
+    fn test_lp_concentration_mixed_tracking() {
+        // Tests that anonymous removals cannot burn LP tokens credited to depositors.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool
+            .add_liquidity_for(Address(1), TokenAmount(100000000))
+            .unwrap();
+        assert_eq!(
+            lp_pool.remove_liquidity(LpTokenAmount(100000000)),
+            Err(Error::InsufficientLiquidity)
+        );
+
+        // Untracked tokens can still leave anonymously, but no further
+        lp_pool.add_liquidity(TokenAmount(20000000)).unwrap();
+        assert_eq!(lp_pool.untracked_lp_supply(), LpTokenAmount(20000000));
+        assert_eq!(
+            lp_pool.remove_liquidity(LpTokenAmount(20000001)),
+            Err(Error::InsufficientLiquidity)
+        );
+        lp_pool.remove_liquidity(LpTokenAmount(20000000)).unwrap();
+
+        lp_pool
+            .add_liquidity_for(Address(2), TokenAmount(10000000))
+            .unwrap();
+        assert_eq!(lp_pool.lp_holding(Address(1)), LpTokenAmount(100000000));
+        assert_eq!(lp_pool.lp_token_amount, LpTokenAmount(110000000));
+        assert_eq!(
+            lp_pool.remove_liquidity_for(Address(1), LpTokenAmount(100000001)),
+            Err(Error::InsufficientLiquidity)
+        );
+        let concentration = lp_pool.lp_concentration();
+        assert!(concentration <= 1.0);
+        assert!(
+            (concentration - ((10.0f64 / 11.0).powi(2) + (1.0f64 / 11.0).powi(2))).abs() < 1e-12
+        );
+    }
+
+    #[test]
+    fn test_metrics_sink() {
+        // Tests that an attached sink receives one call per successful operation and none for simulations.
+        #[derive(Debug, Default)]
+        struct MockSink {
+            events: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl MetricsSink for MockSink {
+            fn record_add_liquidity(
+                &self,
+                token_amount: TokenAmount,
+                lp_tokens_minted: LpTokenAmount,
+            ) {
+                let event = format!("add {} {}", token_amount.0, lp_tokens_minted.0);
+                self.events.lock().unwrap().push(event);
+            }
+
+            fn record_swap(&self, volume: StakedTokenAmount, fee: Percentage) {
+                let event = format!("swap {} {}", volume.0, fee.0);
+                self.events.lock().unwrap().push(event);
+            }
+        }
+
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let sink = Arc::new(MockSink::default());
+        lp_pool.set_metrics_sink(Some(sink.clone()));
+
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let _ = lp_pool.swap(StakedTokenAmount(0)); // Rejected operations are not recorded
+        lp_pool.remove_liquidity(LpTokenAmount(10000000)).unwrap(); // Uses the no-op default
+        lp_pool
+            .preview_with_fees(StakedTokenAmount(6000000), Percentage(0), Percentage(0))
+            .unwrap();
+
+        let events = sink.events.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![
+                "add 100000000 100000000".to_string(),
+                "swap 6000000 90000".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_remove_before_elevated() {
+        // Tests that removing the returned LP amount leaves the token balance exactly at the target.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let lp_token_amount = lp_pool.max_remove_before_elevated();
+        assert_eq!(lp_token_amount, LpTokenAmount(10000000));
+
+        lp_pool.remove_liquidity(lp_token_amount).unwrap();
+        assert_eq!(lp_pool.token_amount, lp_pool.liquidity_target);
+        assert_eq!(lp_pool.max_remove_before_elevated(), LpTokenAmount(0));
+    }
+
+    #[test]
+    fn test_summary() {
+        // Tests that the summary fields match the pool balances and individual getters.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+
+        let summary = lp_pool.summary();
+        assert_eq!(
+            summary.token_amount,
+            lp_pool.token_amount.0 as f64 / SCALE as f64
+        );
+        assert_eq!(summary.st_token_amount, 6.0);
+        assert_eq!(summary.lp_token_amount, 100.0);
+        assert_eq!(summary.price, 1.5);
+        assert_eq!(summary.min_fee, Percentage(90000));
+        assert_eq!(summary.max_fee, Percentage(900000));
+        assert_eq!(summary.liquidity_target, 90.0);
+        assert_eq!(summary.reserve_ratio, lp_pool.reserve_ratio());
+        assert_eq!(summary.lp_token_price, lp_pool.lp_token_price());
+    }
+
+    #[test]
+    fn test_simulate_price_path() {
+        // Tests that a declining price path leaves the fee unchanged and does not touch the live pool.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(20000000)).unwrap(); // Below target, so the fee is elevated
+        let marginal_fee = lp_pool.marginal_fee();
+        assert!(marginal_fee > lp_pool.min_fee);
+
+        let prices = [Price(1400000), Price(1200000), Price(900000), Price(500000)];
+        let fees = lp_pool.simulate_price_path(&prices);
+        assert_eq!(fees, vec![marginal_fee; prices.len()]);
+        assert_eq!(lp_pool.price, Price(1500000));
+    }
+
+    #[test]
+    fn test_remove_liquidity_rounding() {
+        // Tests that repeated tiny removals can never withdraw more than was deposited.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(1000003)).unwrap();
+        lp_pool.add_liquidity(TokenAmount(7)).unwrap();
+        let deposited = lp_pool.token_amount.0;
+
+        let mut withdrawn = 0;
+        while lp_pool.lp_token_amount.0 > 0 {
+            let token_amount_before = lp_pool.token_amount.0;
+            let burn = LpTokenAmount(lp_pool.lp_token_amount.0.min(3));
+            lp_pool.remove_liquidity(burn).unwrap();
+            withdrawn += token_amount_before - lp_pool.token_amount.0;
+            assert!(withdrawn <= deposited);
+        }
+        assert_eq!(withdrawn + lp_pool.token_amount.0, deposited);
+        assert_eq!(
+            lp_pool.remove_liquidity(LpTokenAmount(1)),
+            Err(Error::InsufficientLiquidity)
+        );
+    }
+
+    #[test]
+    fn test_compare_fee_curves() {
+        // Tests that the quadratic curve earns more than the linear one below the target.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+
+        // Swapping 30 staked tokens leaves 55 tokens, well below the 90 token target
+        let revenues = lp_pool
+            .compare_fee_curves(StakedTokenAmount(30000000))
+            .unwrap();
+        assert_eq!(revenues.len(), 2);
+        assert_eq!(revenues[0].0, FeeCurve::Linear);
+        assert_eq!(revenues[1].0, FeeCurve::Quadratic);
+        assert_eq!(
+            revenues[0].1,
+            lp_pool.fee_amount(StakedTokenAmount(30000000)).unwrap().0 as f64 / SCALE as f64
+        );
+        assert!(revenues[1].1 > revenues[0].1);
+
+        // Above the target both curves charge the minimum fee
+        let revenues = lp_pool
+            .compare_fee_curves(StakedTokenAmount(1000000))
+            .unwrap();
+        assert_eq!(revenues[0].1, revenues[1].1);
+        assert_eq!(
+            lp_pool.compare_fee_curves(StakedTokenAmount(0)),
+            Err(Error::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_output_range() {
+        // Tests that a plain swap's output falls between the max-fee and min-fee outputs.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let (min_fee_output, max_fee_output) =
+            lp_pool.output_range(StakedTokenAmount(30000000)).unwrap();
+        assert!((min_fee_output - 40.95).abs() < 1e-9);
+        assert!((max_fee_output - 4.5).abs() < 1e-9);
+
+        let mut waived_pool = lp_pool.clone();
+        let output = lp_pool.swap(StakedTokenAmount(30000000)).unwrap();
+        assert!(max_fee_output <= output && output <= min_fee_output);
+
+        // A waived swap pays out the full value, above the range
+        waived_pool.set_fee_waiver_until(Some(10));
+        let output = waived_pool.swap_at(StakedTokenAmount(30000000), 0).unwrap();
+        assert!((output - 45.0).abs() < 1e-9);
+        assert!(output > min_fee_output);
+        assert_eq!(
+            lp_pool.output_range(StakedTokenAmount(0)),
+            Err(Error::InvalidInput)
+        );
+
+        // A swap the pool cannot service has no range either
+        assert_eq!(
+            lp_pool.output_range(StakedTokenAmount(1000000000)),
+            Err(Error::InsufficientLiquidity)
+        );
+        assert_eq!(
+            lp_pool.swap(StakedTokenAmount(1000000000)),
+            Err(Error::InsufficientLiquidity)
+        );
+    }
+
+    #[test]
+    fn test_min_balance_for_swap() {
+        // Tests that a pool seeded with the minimum balance services the swap and one seeded
+        // with a unit less does not.
+        let lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        // 30 staked tokens are worth 45 tokens, of which 10% is paid out at the maximum fee
+        let min_balance = lp_pool.min_balance_for_swap(StakedTokenAmount(30000000));
+        assert_eq!(min_balance, TokenAmount(4500000));
+
+        let mut seeded_pool = lp_pool.clone();
+        seeded_pool.add_liquidity(min_balance).unwrap();
+        assert!((seeded_pool.swap(StakedTokenAmount(30000000)).unwrap() - 4.5).abs() < 1e-9);
+
+        let mut short_pool = lp_pool.clone();
+        short_pool
+            .add_liquidity(TokenAmount(min_balance.0 - 1))
+            .unwrap();
+        assert_eq!(
+            short_pool.swap(StakedTokenAmount(30000000)),
+            Err(Error::InsufficientLiquidity)
+        );
+        assert_eq!(
+            lp_pool.min_balance_for_swap(StakedTokenAmount(0)),
+            TokenAmount(0)
+        );
+
+        // Without a liquidity target even a drained pool charges only the minimum fee
+        let untargeted_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(0),
+        )
+        .unwrap();
+        let min_balance = untargeted_pool.min_balance_for_swap(StakedTokenAmount(10000000));
+        assert_eq!(min_balance, TokenAmount(13650000));
+        let mut seeded_pool = untargeted_pool.clone();
+        seeded_pool.add_liquidity(min_balance).unwrap();
+        assert!((seeded_pool.swap(StakedTokenAmount(10000000)).unwrap() - 13.65).abs() < 1e-9);
+        let mut short_pool = untargeted_pool.clone();
+        short_pool
+            .add_liquidity(TokenAmount(min_balance.0 - 1))
+            .unwrap();
+        assert_eq!(
+            short_pool.swap(StakedTokenAmount(10000000)),
+            Err(Error::InsufficientLiquidity)
+        );
+    }
+
+    #[test]
+    fn test_swaps_until_exhausted() {
+        // Tests that the simulated swap count matches running the swaps manually.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let swaps = lp_pool
+            .swaps_until_exhausted(StakedTokenAmount(10000000))
+            .unwrap();
+        assert!(swaps > 0);
+
+        let mut manual_swaps = 0;
+        while lp_pool.swap(StakedTokenAmount(10000000)).is_ok() {
+            manual_swaps += 1;
+        }
+        assert_eq!(swaps, manual_swaps);
+
+        // Swaps of a few scaled units against a 10 token pool would take millions of steps to count
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(10000000)).unwrap();
+        assert_eq!(
+            lp_pool.swaps_until_exhausted(StakedTokenAmount(10)),
+            Err(Error::SimulationLimitExceeded)
+        );
+    }
+
+    #[test]
+    fn test_swap_for_rebate() {
+        // Tests that a rebated address receives more than a non-rebated one for the same swap.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool
+            .set_fee_rebate(Address(1), Some(Percentage(500000)))
+            .unwrap();
+
+        let rebated = lp_pool
+            .hypothetical()
+            .swap_for(Address(1), StakedTokenAmount(6000000))
+            .unwrap();
+        let regular = lp_pool
+            .swap_for(Address(2), StakedTokenAmount(6000000))
+            .unwrap();
+        assert!((rebated - 9.0 * (1.0 - 0.045)).abs() < 1e-9); // Half of the 9% fee is rebated
+        assert!((regular - 8.19).abs() < 1e-9);
+        assert_eq!(
+            lp_pool.set_fee_rebate(Address(1), Some(Percentage(1000001))),
+            Err(Error::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_rolling_volume() {
+        // Tests that the rolling volume only sums swaps inside the window.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap_at(StakedTokenAmount(1000000), 1000).unwrap();
+        lp_pool.swap_at(StakedTokenAmount(2000000), 50000).unwrap();
+        lp_pool.swap_at(StakedTokenAmount(3000000), 90000).unwrap();
+        assert_eq!(lp_pool.operation_log().len(), 3);
+
+        let day = 24 * 60 * 60;
+        assert_eq!(
+            lp_pool.rolling_volume(90000, day),
+            StakedTokenAmount(5000000)
+        );
+        // The window ending at 87400 excludes both the swap at 1000 and the later one at 90000
+        assert_eq!(
+            lp_pool.rolling_volume(87400, day),
+            StakedTokenAmount(2000000)
+        );
+        assert_eq!(lp_pool.rolling_volume(200000, day), StakedTokenAmount(0));
+    }
+
+    #[test]
+    fn test_swap_exact_liquidity_boundary() {
+        // Tests that a swap whose output rounds up to exactly the full balance drains the pool without panicking.
+        let mut lp_pool =
+            LpPool::init(Price(300000), Percentage(0), Percentage(0), TokenAmount(0)).unwrap();
+        lp_pool.add_liquidity(TokenAmount(1000000)).unwrap();
+        let result = lp_pool.swap(StakedTokenAmount(3333333)); // 0.9999999 tokens, rounding to 1000000 scaled units
+        assert!(result.is_ok());
+        assert_eq!(lp_pool.token_amount, TokenAmount(0));
+        assert_eq!(
+            lp_pool.swap(StakedTokenAmount(3333333)),
+            Err(Error::InsufficientLiquidity)
+        );
+    }
+
+    #[test]
+    fn test_lp_price_after() {
+        // Tests that the previewed LP price moves by the staked balance times the price change per LP token.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(20000000)).unwrap();
+        let lp_price = lp_pool.lp_token_price();
+
+        let lp_price_after = lp_pool.lp_price_after(Price(2000000));
+        let expected_change = 20.0 * (2.0 - 1.5) / 100.0; // Staked balance times price change per LP token
+        assert!((lp_price_after - lp_price - expected_change).abs() < 1e-12);
+        assert_eq!(lp_pool.lp_token_price(), lp_price);
+    }
+
+    #[test]
+    fn test_custom_fee_strategy() {
+        // Tests that a custom constant-fee strategy overrides the default linear curve.
+        #[derive(Debug)]
+        struct ConstantFee(Percentage);
+
+        impl FeeStrategy for ConstantFee {
+            fn fee(
+                &self,
+                _: TokenAmount,
+                _: TokenAmount,
+                _: Percentage,
+                _: Percentage,
+            ) -> Percentage {
+                self.0
+            }
+        }
+
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.fee_at_balance(TokenAmount(0)), Percentage(900000));
+
+        lp_pool.set_fee_strategy(Arc::new(ConstantFee(Percentage(10000))));
+        assert_eq!(lp_pool.fee_at_balance(TokenAmount(0)), Percentage(10000));
+        let result = lp_pool.swap(StakedTokenAmount(30000000)).unwrap(); // Far below target, still 1%
+        assert!((result - 45.0 * 0.99).abs() < 1e-9);
+
+        // The strategy applies even when the configured min and max fees are both zero
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(0),
+            Percentage(0),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.set_fee_strategy(Arc::new(ConstantFee(Percentage(100000))));
+        assert_eq!(
+            lp_pool.min_balance_for_swap(StakedTokenAmount(6000000)),
+            TokenAmount(8100000)
+        );
+        let result = lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert!((result - 9.0 * 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_backing_per_lp() {
+        // Tests that the per-LP backing times the LP supply reconstructs the pool balances within rounding.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(
+            lp_pool.backing_per_lp(),
+            (TokenAmount(1000000), StakedTokenAmount(0))
+        );
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(7000000)).unwrap();
+        lp_pool.add_liquidity(TokenAmount(13000000)).unwrap();
+
+        let (token_backing, staked_backing) = lp_pool.backing_per_lp();
+        let lp_supply = lp_pool.lp_token_amount.0;
+        let tolerance = lp_supply / SCALE as Balance + 1; // Up to one scaled unit lost per whole LP token
+        let reconstructed_tokens = token_backing.0 * lp_supply / SCALE as Balance;
+        let reconstructed_staked = staked_backing.0 * lp_supply / SCALE as Balance;
+        assert!(lp_pool.token_amount.0 - reconstructed_tokens <= tolerance);
+        assert!(lp_pool.st_token_amount.0 - reconstructed_staked <= tolerance);
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        // Tests that a paused pool rejects state-changing operations but still answers queries.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.pause();
+        assert!(lp_pool.is_paused());
+        assert_eq!(
+            lp_pool.add_liquidity(TokenAmount(10000000)),
+            Err(Error::Paused)
+        );
+        assert_eq!(
+            lp_pool.remove_liquidity(LpTokenAmount(10000000)),
+            Err(Error::Paused)
+        );
+        assert_eq!(lp_pool.swap(StakedTokenAmount(6000000)), Err(Error::Paused));
+        assert_eq!(lp_pool.total_value(), 100.0);
+        assert!(
+            lp_pool
+                .swaps_until_exhausted(StakedTokenAmount(6000000))
+                .unwrap()
+                > 0
+        ); // Simulations still work
+        assert!(LpPool::from_bytes(&lp_pool.to_bytes()).unwrap().is_paused());
+
+        lp_pool.resume();
+        assert!(lp_pool.add_liquidity(TokenAmount(10000000)).is_ok());
+        assert!(lp_pool.remove_liquidity(LpTokenAmount(10000000)).is_ok());
+        assert!(lp_pool.swap(StakedTokenAmount(6000000)).is_ok());
+    }
+
+    #[test]
+    fn test_max_mintable_lp() {
+        // Tests that the mintable LP amount matches depositing the remaining headroom under the cap.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.max_mintable_lp(), LpTokenAmount(Balance::MAX));
+        lp_pool.set_max_token_amount(Some(TokenAmount(150000000)));
+        assert_eq!(lp_pool.max_mintable_lp(), LpTokenAmount(150000000));
+
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let max_mintable_lp = lp_pool.max_mintable_lp();
+        let headroom = TokenAmount(150000000 - lp_pool.token_amount.0);
+        assert_eq!(
+            lp_pool.add_liquidity(TokenAmount(headroom.0 + 1)),
+            Err(Error::CapExceeded)
+        );
+        let lp_supply_before = lp_pool.lp_token_amount.0;
+        lp_pool.add_liquidity(headroom).unwrap();
+        let minted = lp_pool.lp_token_amount.0 - lp_supply_before;
+        assert!(minted.abs_diff(max_mintable_lp.0) <= 1);
+        assert_eq!(lp_pool.max_mintable_lp(), LpTokenAmount(0));
+    }
+
+    #[test]
+    fn test_implied_price() {
+        // Tests that the implied price is undefined before swaps and sits below the stored price after them.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.implied_price(), None);
+
+        let first = lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let implied_price = lp_pool.implied_price().unwrap();
+        assert!((implied_price - first / 6.0).abs() < 1e-9);
+        assert!((implied_price - 1.5 * (1.0 - 0.09)).abs() < 1e-9); // Spot less the minimum fee
+
+        lp_pool.swap(StakedTokenAmount(30000000)).unwrap(); // Elevated fees push the implied price further down
+        assert!(lp_pool.implied_price().unwrap() < implied_price);
+        assert_eq!(lp_pool.stats().swap_count, 2);
+    }
+
+    #[test]
+    fn test_fee_amount() {
+        // Tests that the fee in tokens equals the gross swap value minus the tokens actually received.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let staked_token_amount = StakedTokenAmount(20000000);
+        let fee_amount = lp_pool.fee_amount(staked_token_amount).unwrap();
+        let fee_rate = lp_pool.fee_for_amount(staked_token_amount).unwrap();
+        let gross = lp_pool.value_in_tokens(staked_token_amount).0;
+        assert_eq!(
+            fee_amount.0,
+            gross * fee_rate.0 as Balance / SCALE as Balance
+        );
+
+        let token_amount_before = lp_pool.token_amount.0;
+        lp_pool.swap(staked_token_amount).unwrap();
+        let net = token_amount_before - lp_pool.token_amount.0;
+        assert_eq!(fee_amount.0, gross - net);
+    }
+
+    #[test]
+    fn test_zero_fee_swap_is_exact() {
+        // Tests that a pool with both fees at zero pays out exactly staked * price.
+        let mut lp_pool = LpPool::init(
+            Price(1333333),
+            Percentage(0),
+            Percentage(0),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let token_amount_before = lp_pool.token_amount.0;
+        lp_pool.swap(StakedTokenAmount(7777777)).unwrap();
+        let expected = (7777777 * 1333333 + SCALE as Balance / 2 - 1) / SCALE as Balance;
+        assert_eq!(token_amount_before - lp_pool.token_amount.0, expected);
+        assert_eq!(
+            lp_pool.fee_for_amount(StakedTokenAmount(7777777)),
+            Ok(Percentage(0))
+        );
+    }
+
+    #[test]
+    fn test_estimated_cost_ordering() {
+        // Tests that swaps cost more than withdrawals, which cost more than deposits.
+        let add = LpPool::estimated_cost(&Operation::AddLiquidity(TokenAmount(1000000)));
+        let remove = LpPool::estimated_cost(&Operation::RemoveLiquidity(LpTokenAmount(1000000)));
+        let swap = LpPool::estimated_cost(&Operation::Swap(StakedTokenAmount(1000000)));
+        assert!(add < remove);
+        assert!(remove < swap);
+        assert_eq!(
+            LpPool::estimated_cost(&Operation::Swap(StakedTokenAmount(1))),
+            swap
+        );
+    }
+
+    #[test]
+    fn test_swap_max_impact_allowed() {
+        // Tests that a swap whose price impact is within the limit executes like a regular swap.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let mut reference_pool = lp_pool.clone();
+        let impact = lp_pool.price_impact(StakedTokenAmount(6000000)).unwrap();
+        assert_eq!(
+            lp_pool.swap_max_impact(StakedTokenAmount(6000000), impact),
+            reference_pool.swap(StakedTokenAmount(6000000))
+        );
+        assert_eq!(lp_pool.token_amount, reference_pool.token_amount);
+    }
+
+    #[test]
+    fn test_swap_max_impact_rejected() {
+        // Tests that a swap whose price impact exceeds the limit fails and leaves the pool unchanged.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let impact = lp_pool.price_impact(StakedTokenAmount(60000000)).unwrap();
+        assert!(impact.0 > 90000);
+        assert_eq!(
+            lp_pool.swap_max_impact(StakedTokenAmount(60000000), Percentage(impact.0 - 1)),
+            Err(Error::ExcessiveImpact)
+        );
+        assert_eq!(lp_pool.token_amount, TokenAmount(100000000));
+        assert_eq!(lp_pool.st_token_amount, StakedTokenAmount(0));
+    }
+
+    #[test]
+    fn test_price_delta() {
+        // Tests that the pool's value sensitivity to price equals its staked balance after swaps.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.price_delta(), 0.0);
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(30000000)).unwrap();
+        assert_eq!(lp_pool.price_delta(), 36.0);
+
+        let value_before = lp_pool.total_value();
+        lp_pool.update_price(Price(1600000), 0).unwrap();
+        let value_change = lp_pool.total_value() - value_before;
+        assert!((value_change / 0.1 - lp_pool.price_delta()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_add_liquidity_batch() {
+        // Tests that a batch of deposits mints the same LP tokens as applying them one by one.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let mut reference_pool = lp_pool.clone();
+        let deposits = [
+            TokenAmount(100000000),
+            TokenAmount(50000000),
+            TokenAmount(25000000),
+        ];
+        let minted = lp_pool.add_liquidity_batch(&deposits).unwrap();
+        assert_eq!(minted, vec![100.0, 50.0, 25.0]);
+        for (deposit, lp_minted) in deposits.iter().zip(&minted) {
+            assert_eq!(reference_pool.add_liquidity(*deposit).unwrap(), *lp_minted);
+        }
+        assert_eq!(lp_pool.lp_token_amount, LpTokenAmount(175000000));
+        assert_eq!(lp_pool.token_amount, TokenAmount(175000000));
+        assert_eq!(lp_pool.operation_count(), 3);
+    }
+
+    #[test]
+    fn test_add_liquidity_batch_is_atomic() {
+        // Tests that a batch containing a deposit over the cap leaves the pool untouched.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.set_max_token_amount(Some(TokenAmount(120000000)));
+        let deposits = [TokenAmount(100000000), TokenAmount(50000000)];
+        assert_eq!(
+            lp_pool.add_liquidity_batch(&deposits),
+            Err(Error::CapExceeded)
+        );
+        assert_eq!(lp_pool.token_amount, TokenAmount(0));
+        assert_eq!(lp_pool.lp_token_amount, LpTokenAmount(0));
+        assert_eq!(lp_pool.operation_count(), 0);
+    }
+
+    #[test]
+    fn test_lp_for_token_out_round_trip() {
+        // Tests that burning the computed LP amount pays out exactly the requested tokens.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let desired_tokens = TokenAmount(33333333);
+        let lp_to_burn = lp_pool.lp_for_token_out(desired_tokens).unwrap();
+
+        let mut smaller_burn_pool = lp_pool.clone();
+        let (tokens_received, _) = lp_pool.remove_liquidity(lp_to_burn).unwrap();
+        assert_eq!(tokens_received, 33.333333);
+        let (tokens_received, _) = smaller_burn_pool
+            .remove_liquidity(LpTokenAmount(lp_to_burn.0 - 1))
+            .unwrap();
+        assert!(tokens_received < 33.333333);
+
+        assert_eq!(
+            lp_pool.lp_for_token_out(TokenAmount(lp_pool.token_amount.0 + 1)),
+            Err(Error::InsufficientLiquidity)
+        );
+    }
+
+    #[test]
+    fn test_payout_rounding_at_half() {
+        // Tests that swap payouts landing exactly on half a unit round down in the pool's favor.
+        assert_eq!(round_payout(2.5), 2);
+        assert_eq!(round_payout(2.500001), 3);
+        assert_eq!(round_payout(2.4), 2);
+        assert_eq!(round_payout(0.0), 0);
+        assert_eq!(round_payout_scaled(2500000, 1), 2);
+        assert_eq!(round_payout_scaled(2500001, 1), 3);
+
+        // 1 staked unit at a price of 1.5 is worth exactly 1.5 token units
+        let mut lp_pool =
+            LpPool::init(Price(1500000), Percentage(0), Percentage(0), TokenAmount(0)).unwrap();
+        lp_pool.add_liquidity(TokenAmount(1000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(1)).unwrap();
+        assert_eq!(lp_pool.token_amount, TokenAmount(999999));
+    }
+
+    #[test]
+    fn test_config() {
+        // Tests that the pool's config holds the values passed to init, unaffected by its balances.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let config = PoolConfig {
+            price: Price(1500000),
+            min_fee: Percentage(90000),
+            max_fee: Percentage(900000),
+            liquidity_target: TokenAmount(90000000),
+        };
+        assert_eq!(lp_pool.config(), config);
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert_eq!(lp_pool.config(), config);
+    }
+
+    #[test]
+    fn test_fee_integral_flat_above_target() {
+        // Tests that above the liquidity target the fee integral is the flat min-fee gross-up.
+        let lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let fees = lp_pool.fee_integral(TokenAmount(150000000), TokenAmount(100000000));
+        assert!((fees - 50.0 * 0.09 / 0.91).abs() < 1e-9);
+        assert_eq!(
+            lp_pool.fee_integral(TokenAmount(100000000), TokenAmount(150000000)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_quote_exact_out_matches_small_swaps() {
+        // Tests that the integral-based exact-out quote matches many small exact-in swaps crossing the target.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(1000),
+            Percentage(90000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        let staked_needed = lp_pool.quote_exact_out(TokenAmount(30000000)).unwrap();
+
+        let steps = 1000;
+        let token_amount_before = lp_pool.token_amount.0;
+        for step in 0..steps {
+            // Spread the remainder over the first swaps so the chunks add up to the quote
+            let chunk = staked_needed.0 / steps + Balance::from(step < staked_needed.0 % steps);
+            lp_pool.swap(StakedTokenAmount(chunk)).unwrap();
+        }
+        let tokens_out = (token_amount_before - lp_pool.token_amount.0) as f64 / SCALE as f64;
+        assert!((tokens_out - 30.0).abs() < 1e-3);
+
+        // A flat fee at the final balance would overcharge the swap
+        let flat_fee = LinearFeeStrategy
+            .fee(
+                TokenAmount(70000000),
+                TokenAmount(90000000),
+                Percentage(1000),
+                Percentage(90000),
+            )
+            .to_f64();
+        let flat_staked_needed = 30.0 / (1.0 - flat_fee) / 1.5;
+        assert!(flat_staked_needed - staked_needed.0 as f64 / SCALE as f64 > 0.1);
+
+        // The quote follows a non-linear fee strategy too
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(1000),
+            Percentage(90000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.set_fee_strategy(Arc::new(QuadraticFeeStrategy));
+        let staked_needed = lp_pool.quote_exact_out(TokenAmount(30000000)).unwrap();
+
+        let token_amount_before = lp_pool.token_amount.0;
+        for step in 0..steps {
+            let chunk = staked_needed.0 / steps + Balance::from(step < staked_needed.0 % steps);
+            lp_pool.swap(StakedTokenAmount(chunk)).unwrap();
+        }
+        let tokens_out = (token_amount_before - lp_pool.token_amount.0) as f64 / SCALE as f64;
+        assert!((tokens_out - 30.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_normalized_state() {
+        // Tests that pools built from scaled units and from natural values normalize identically.
+        let mut scaled_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        scaled_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        scaled_pool.swap(StakedTokenAmount(6000000)).unwrap();
+
+        let mut natural_pool = LpPool::init(
+            Price::from(1.5),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount::from(90.0),
+        )
+        .unwrap();
+        natural_pool
+            .add_liquidity(TokenAmount::from(100.0))
+            .unwrap();
+        natural_pool.swap(StakedTokenAmount::from(6.0)).unwrap();
+
+        let normalized_state = scaled_pool.normalized_state();
+        assert_eq!(normalized_state, natural_pool.normalized_state());
+        assert_eq!(normalized_state.price, 1.5);
+        assert_eq!(normalized_state.st_token_amount, 6.0);
+        assert_eq!(normalized_state.liquidity_target, 90.0);
+    }
+
+    #[test]
+    fn test_price_to_preserve_lp_value() {
+        // Tests that moving to the returned price after the swap leaves the LP token price unchanged.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let lp_price_before = lp_pool.lp_token_price();
+
+        let price = lp_pool
+            .price_to_preserve_lp_value(StakedTokenAmount(20000000))
+            .unwrap();
+        assert!(price.0 < 1500000);
+        lp_pool.swap(StakedTokenAmount(20000000)).unwrap();
+        assert!(lp_pool.lp_token_price() > lp_price_before);
+        lp_pool.update_price(price, 0).unwrap();
+        assert!((lp_pool.lp_token_price() - lp_price_before).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_leverage() {
+        // Tests that leverage rises as swaps drain tokens and add staked exposure.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.leverage(), 0.0);
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.leverage(), 0.0);
+
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let leverage_after_first_swap = lp_pool.leverage();
+        assert!(
+            (leverage_after_first_swap - 9.0 / lp_pool.token_amount.0 as f64 * 1e6).abs() < 1e-12
+        );
+        lp_pool.swap(StakedTokenAmount(30000000)).unwrap();
+        assert!(lp_pool.leverage() > leverage_after_first_swap);
+    }
+
+    #[test]
+    fn test_reset_stats() {
+        // Tests that resetting stats clears swap count, volume and fees but leaves the pool's state alone.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let stats = lp_pool.stats();
+        assert_eq!(stats.swap_count, 2);
+        assert_eq!(stats.staked_volume, StakedTokenAmount(12000000));
+        assert_eq!(
+            stats.fees_collected.0,
+            18000000 - (100000000 - lp_pool.token_amount.0)
+        );
+
+        let summary = lp_pool.summary();
+        lp_pool.reset_stats();
+        assert_eq!(lp_pool.stats().swap_count, 0);
+        assert_eq!(lp_pool.stats().staked_volume, StakedTokenAmount(0));
+        assert_eq!(lp_pool.stats().tokens_paid, TokenAmount(0));
+        assert_eq!(lp_pool.stats().fees_collected, TokenAmount(0));
+        assert_eq!(lp_pool.implied_price(), None);
+        assert_eq!(lp_pool.summary(), summary);
+        assert_eq!(lp_pool.operation_count(), 3);
+    }
+
+    #[test]
+    fn test_from_raw_params() {
+        // Tests that a pool built from raw integer params matches the one built from f64 values.
+        let params = PoolParamsRaw {
+            price: 1500000,
+            min_fee_bps: 10,
+            max_fee_bps: 900,
+            liquidity_target: 90000000,
+        };
+        let mut raw_pool = LpPool::from_raw_params(params).unwrap();
+        let mut f64_pool = LpPool::init(
+            Price::from(1.5),
+            Percentage((0.001 * SCALE as f64) as u64),
+            Percentage((0.09 * SCALE as f64) as u64),
+            TokenAmount::from(90.0),
+        )
+        .unwrap();
+        assert_eq!(raw_pool.config(), f64_pool.config());
+        assert_eq!(raw_pool.config().min_fee, Percentage(1000));
+        assert_eq!(raw_pool.config().max_fee, Percentage(90000));
+
+        raw_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        f64_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(
+            raw_pool.swap(StakedTokenAmount(6000000)),
+            f64_pool.swap(StakedTokenAmount(6000000))
+        );
+
+        assert_eq!(
+            LpPool::from_raw_params(PoolParamsRaw {
+                max_fee_bps: 10001,
+                ..params
+            })
+            .unwrap_err(),
+            Error::InvalidInput
+        );
+        assert_eq!(
+            LpPool::from_raw_params(PoolParamsRaw { price: 0, ..params }).unwrap_err(),
+            Error::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_fee_split() {
+        // Tests that realized and unrealized fees add up to the staked balance's value less its cost.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.fee_split(), (0.0, 0.0));
+        let tokens_paid = lp_pool.swap(StakedTokenAmount(6000000)).unwrap()
+            + lp_pool.swap(StakedTokenAmount(20000000)).unwrap();
+
+        // At an unchanged price every fee is realized
+        let (realized, unrealized) = lp_pool.fee_split();
+        assert!((realized - lp_pool.stats().fees_collected.0 as f64 / SCALE as f64).abs() < 1e-9);
+        assert_eq!(unrealized, 0.0);
+
+        lp_pool.update_price(Price(1600000), 0).unwrap();
+        let (realized_after_move, unrealized) = lp_pool.fee_split();
+        assert_eq!(realized_after_move, realized);
+        assert!((unrealized - 26.0 * 0.1).abs() < 1e-9);
+        assert!((realized + unrealized - (26.0 * 1.6 - tokens_paid)).abs() < 1e-6);
+
+        // Withdrawing half the liquidity takes half the accrued fees with it
+        lp_pool.remove_liquidity(LpTokenAmount(50000000)).unwrap();
+        let (realized_after_removal, unrealized_after_removal) = lp_pool.fee_split();
+        assert!((realized_after_removal - realized / 2.0).abs() < 1e-5);
+        assert!((unrealized_after_removal - unrealized / 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_round_trip_cost() {
+        // Tests that an immediate deposit and withdrawal costs no more than rounding, whatever the swap fees.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.round_trip_cost(TokenAmount(10000000)), Ok(0.0));
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+
+        let cost = lp_pool.round_trip_cost(TokenAmount(10000000)).unwrap();
+        assert!(cost >= 0.0);
+        assert!(cost < 1e-5);
+        assert_eq!(lp_pool.token_amount, TokenAmount(91810000)); // The simulation leaves the pool untouched
+    }
+
+    #[test]
+    fn test_fee_quote() {
+        // Tests that the fee rate and fee amount in a quote describe the same fee.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        for staked_token_amount in [StakedTokenAmount(6000000), StakedTokenAmount(30000000)] {
+            let fee_quote = lp_pool.fee_quote(staked_token_amount).unwrap();
+            let gross = lp_pool.value_in_tokens(staked_token_amount).0;
+            assert_eq!(
+                fee_quote.amount.0,
+                gross * fee_quote.rate.0 as Balance / SCALE as Balance
+            );
+            assert_eq!(
+                fee_quote.rate,
+                lp_pool.fee_for_amount(staked_token_amount).unwrap()
+            );
+            assert_eq!(
+                fee_quote.amount,
+                lp_pool.fee_amount(staked_token_amount).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_price_drawdown() {
+        // Tests that the drawdown is the fee surplus over deposits relative to the staked value.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.max_price_drawdown(), 1.0);
+
+        // 91.81 tokens and 6 staked tokens worth 9 against 100 deposited: 0.81 of surplus
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let drawdown = lp_pool.max_price_drawdown();
+        assert!((drawdown - 0.81 / 9.0).abs() < 1e-12);
+
+        lp_pool
+            .update_price(Price((1500000.0 * (1.0 - drawdown)) as u64 + 1), 0)
+            .unwrap();
+        assert!(lp_pool.total_value() >= 100.0);
+        assert!(lp_pool.max_price_drawdown() < 1e-6);
+        lp_pool.update_price(Price(1000000), 0).unwrap();
+        assert_eq!(lp_pool.max_price_drawdown(), 0.0);
+
+        // Withdrawing half the liquidity halves the deposits still owed
+        lp_pool.update_price(Price(1500000), 0).unwrap();
+        lp_pool.remove_liquidity(LpTokenAmount(50000000)).unwrap();
+        assert!((lp_pool.max_price_drawdown() - drawdown).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_accrue_rewards() {
+        // Tests that accrued rewards raise the LP token price without minting LP tokens.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(
+            lp_pool.accrue_rewards(StakedTokenAmount(1000000)),
+            Err(Error::InvalidInput)
+        );
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let lp_price_before = lp_pool.lp_token_price();
+
+        // One staked token worth 1.5 tokens spread over 100 LP tokens
+        lp_pool.accrue_rewards(StakedTokenAmount(1000000)).unwrap();
+        assert_eq!(lp_pool.lp_token_amount, LpTokenAmount(100000000));
+        assert_eq!(lp_pool.st_token_amount, StakedTokenAmount(7000000));
+        assert!((lp_pool.lp_token_price() - lp_price_before - 0.015).abs() < 1e-12);
+        assert_eq!(
+            lp_pool.accrue_rewards(StakedTokenAmount(0)),
+            Err(Error::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_liquidity_target_fraction() {
+        // Tests that a liquidity target set as a share of total value tracks the pool's value.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(
+            lp_pool.set_liquidity_target_fraction(Some(Percentage(1000001))),
+            Err(Error::InvalidInput)
+        );
+        lp_pool
+            .set_liquidity_target_fraction(Some(Percentage(900000)))
+            .unwrap();
+        assert_eq!(lp_pool.effective_liquidity_target(), TokenAmount(90000000));
+
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.effective_liquidity_target(), TokenAmount(180000000));
+        assert_eq!(lp_pool.marginal_fee(), lp_pool.min_fee);
+        lp_pool.swap(StakedTokenAmount(30000000)).unwrap();
+        let expected_target = lp_pool.total_value() * 0.9 * SCALE as f64;
+        assert!((lp_pool.effective_liquidity_target().0 as f64 - expected_target).abs() <= 1.0);
+        // The token balance is still above the fixed target but below 90% of the value
+        assert!(lp_pool.token_amount.0 > lp_pool.liquidity_target.0);
+        assert!(lp_pool.marginal_fee() > lp_pool.min_fee);
+
+        lp_pool.set_liquidity_target_fraction(None).unwrap();
+        assert_eq!(lp_pool.effective_liquidity_target(), TokenAmount(90000000));
+    }
+
+    #[test]
+    fn test_detect_jit_liquidity() {
+        // Tests that a swap between another actor's deposit and withdrawal is flagged.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let (provider, swapper) = (Address(1), Address(2));
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap_at(StakedTokenAmount(1000000), 10).unwrap();
+
+        lp_pool
+            .add_liquidity_for_at(provider, TokenAmount(500000000), 20)
+            .unwrap();
+        lp_pool
+            .swap_for_at(swapper, StakedTokenAmount(6000000), 20)
+            .unwrap();
+        lp_pool
+            .remove_liquidity_for_at(provider, lp_pool.lp_holding(provider), 20)
+            .unwrap();
+        assert_eq!(lp_pool.operation_log()[2].actor, Some(swapper));
+        assert_eq!(lp_pool.detect_jit_liquidity(), vec![2]);
+
+        // The provider's own swap between its deposit and withdrawal is not flagged
+        lp_pool
+            .add_liquidity_for_at(provider, TokenAmount(10000000), 30)
+            .unwrap();
+        lp_pool
+            .swap_for_at(provider, StakedTokenAmount(1000000), 30)
+            .unwrap();
+        lp_pool
+            .remove_liquidity_for_at(provider, lp_pool.lp_holding(provider), 30)
+            .unwrap();
+        assert_eq!(lp_pool.detect_jit_liquidity(), vec![2]);
+
+        // Liquidity withdrawn before a swap and restored after is not just-in-time liquidity
+        lp_pool
+            .add_liquidity_for_at(provider, TokenAmount(10000000), 40)
+            .unwrap();
+        lp_pool
+            .remove_liquidity_for_at(provider, lp_pool.lp_holding(provider), 40)
+            .unwrap();
+        lp_pool
+            .swap_for_at(swapper, StakedTokenAmount(1000000), 40)
+            .unwrap();
+        lp_pool
+            .add_liquidity_for_at(provider, TokenAmount(10000000), 40)
+            .unwrap();
+        assert_eq!(lp_pool.detect_jit_liquidity(), vec![2]);
+    }
+
+    #[test]
+    fn test_outflow_by_fee_tier() {
+        // Tests that the fee tiers partition the token balance in order of rising fee.
+        #[derive(Debug)]
+        struct StepFee;
+
+        impl FeeStrategy for StepFee {
+            fn fee(
+                &self,
+                balance: TokenAmount,
+                _: TokenAmount,
+                _: Percentage,
+                _: Percentage,
+            ) -> Percentage {
+                match balance.0 {
+                    60000000.. => Percentage(10000),
+                    30000000.. => Percentage(30000),
+                    _ => Percentage(90000),
+                }
+            }
+        }
+
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(1000),
+            Percentage(1010),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert!(lp_pool.outflow_by_fee_tier(Percentage(1)).is_empty());
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+
+        let tiers = lp_pool.outflow_by_fee_tier(Percentage(1));
+        assert_eq!(tiers.len(), 11);
+        assert_eq!(tiers[0], (Percentage(1000), TokenAmount(10000000)));
+        assert!(tiers.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        let total: Balance = tiers.iter().map(|(_, amount)| amount.0).sum();
+        assert_eq!(total, lp_pool.token_amount.0);
+
+        assert_eq!(lp_pool.outflow_by_fee_tier(Percentage(0)), tiers);
+        // A step wider than the fee range leaves a single tier at the highest fee
+        assert_eq!(
+            lp_pool.outflow_by_fee_tier(Percentage(11)),
+            vec![(Percentage(1010), TokenAmount(100000000))]
+        );
+
+        lp_pool.set_fee_strategy(Arc::new(StepFee));
+        assert_eq!(
+            lp_pool.outflow_by_fee_tier(Percentage(1)),
+            vec![
+                (Percentage(10000), TokenAmount(40000000)),
+                (Percentage(30000), TokenAmount(30000000)),
+                (Percentage(90000), TokenAmount(30000000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_outflow_by_fee_tier_default_fees() {
+        // Tests that 1% tiers on the default 9%-90% curve stay few and still partition the balance.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+
+        let tiers = lp_pool.outflow_by_fee_tier(Percentage(10000));
+        assert!(tiers.len() <= 82); // One tier per 1% of the 81% fee range, plus one
+        assert!(tiers.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(tiers
+            .iter()
+            .all(|(fee, _)| fee.0 >= 90000 && fee.0 <= 900000));
+        assert_eq!(tiers.last().unwrap().0, Percentage(900000));
+        let total: Balance = tiers.iter().map(|(_, amount)| amount.0).sum();
+        assert_eq!(total, lp_pool.token_amount.0);
+
+        // The first tier runs from the full balance down to 88.889 tokens, the lowest balance
+        // charged under 10%
+        assert!(tiers[0].0 < Percentage(100000));
+        assert_eq!(tiers[0].1, TokenAmount(100000000 - 88889000));
+    }
+
+    #[test]
+    fn test_sample_fee_curve() {
+        // Tests that the sampled fee curve spans zero to twice the target with the requested points.
+        let lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        let samples = lp_pool.sample_fee_curve(5);
+        assert_eq!(samples.len(), 5);
+        assert_eq!(samples[0], (TokenAmount(0), Percentage(900000)));
+        assert_eq!(samples[2], (TokenAmount(90000000), Percentage(90000)));
+        assert_eq!(samples[4], (TokenAmount(180000000), Percentage(90000)));
+        assert_eq!(lp_pool.sample_fee_curve(1), vec![samples[0]]);
+        assert!(lp_pool.sample_fee_curve(0).is_empty());
+    }
+
+    #[test]
+    fn test_breakeven_period() {
+        // Tests that the period is the round-trip cost divided by the deposit's daily fee income.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.breakeven_period(TokenAmount(10000000), 1.0), Ok(0));
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+
+        // Rounding costs the round trip 1.5e-6 tokens, and the deposit earns 10 / (100.81 + 10)
+        // of the pool's fee income: about 9.02e-8 tokens a day
+        let deposit = TokenAmount(10000000);
+        assert!((lp_pool.round_trip_cost(deposit).unwrap() - 1.5e-6).abs() < 1e-12);
+        assert_eq!(lp_pool.breakeven_period(deposit, 1e-6), Ok(17));
+        assert_eq!(
+            lp_pool.breakeven_period(deposit, 0.0),
+            Err(Error::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_swap_keeps_solvent() {
+        // Tests that the check compares the pool's value after the swap against the threshold.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+
+        // Swapping 6 staked tokens leaves 91.81 tokens and 9 tokens of staked value
+        let amount = StakedTokenAmount(6000000);
+        assert_eq!(
+            lp_pool.swap_keeps_solvent(amount, TokenAmount(100810000)),
+            Ok(true)
+        );
+        assert_eq!(
+            lp_pool.swap_keeps_solvent(amount, TokenAmount(100810001)),
+            Ok(false)
+        );
+        assert_eq!(
+            lp_pool.swap_keeps_solvent(StakedTokenAmount(0), TokenAmount(0)),
+            Err(Error::InvalidInput)
+        );
+        assert_eq!(lp_pool.token_amount, TokenAmount(100000000));
+    }
+
+    #[test]
+    fn test_lp_value_in_staked() {
+        // Tests that the staked-token value of an LP position matches its token value at the price.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+
+        // The whole supply is worth the pool's 100.81 tokens, or 100.81 / 1.5 staked tokens
+        let staked_value = lp_pool.lp_value_in_staked(lp_pool.lp_token_amount).unwrap();
+        assert!((staked_value - 100.81 / 1.5).abs() < 1e-9);
+        let position = LpTokenAmount(25000000);
+        let token_value = 25.0 * lp_pool.lp_token_price();
+        assert!((lp_pool.lp_value_in_staked(position).unwrap() * 1.5 - token_value).abs() < 1e-9);
+
+        let zero_price_pool = lp_pool.at_price(Price(0));
+        assert_eq!(
+            zero_price_pool.lp_value_in_staked(position),
+            Err(Error::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_marginal_mint_rate() {
+        // Tests that the mint rate is the LP supply per token of pool value.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.marginal_mint_rate(), 1.0);
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.add_liquidity(TokenAmount(10000000)).unwrap();
+        assert_eq!(
+            lp_pool.marginal_mint_rate(),
+            lp_pool.lp_token_amount.0 as f64 / lp_pool.token_amount.0 as f64
+        );
+
+        // Fees raise the pool's value, so each token then mints fewer LP tokens
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let rate = lp_pool.marginal_mint_rate();
+        assert!((rate - 110.0 / 110.81).abs() < 1e-12);
+        let minted = lp_pool.add_liquidity(TokenAmount(1000000)).unwrap();
+        assert!((minted - rate).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_zero_price_analytics() {
+        // Tests that methods dividing by the price fail cleanly instead of returning inf or NaN.
+        let mut lp_pool = LpPool::init(
+            Price(0),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(
+            lp_pool.value_in_staked(TokenAmount(1000000)),
+            Err(Error::InvalidInput)
+        );
+        assert_eq!(
+            lp_pool.quote_exact_out(TokenAmount(1000000)),
+            Err(Error::InvalidInput)
+        );
+        assert_eq!(
+            lp_pool.rebalance_swap(Percentage(800000)),
+            Err(Error::InvalidInput)
+        );
+        assert_eq!(
+            lp_pool.fee_breakeven_size(Percentage(500000)),
+            Err(Error::InvalidInput)
+        );
+        assert_eq!(lp_pool.update_price(Price(0), 0), Err(Error::InvalidInput));
+    }
+
+    #[test]
+    fn test_lp_conversion_rate() {
+        // Tests that converting LP tokens between pools preserves their value in tokens.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let mut other = LpPool::init(
+            Price(2000000),
+            Percentage(10000),
+            Percentage(500000),
+            TokenAmount(50000000),
+        )
+        .unwrap();
+        other.add_liquidity(TokenAmount(40000000)).unwrap();
+        other.swap(StakedTokenAmount(5000000)).unwrap();
+        assert_eq!(other.lp_conversion_rate(&other), 1.0);
+
+        // A holder of 10 LP tokens of `other`, whose staked tokens are revalued at 1.5
+        let holding = 10.0;
+        let value = holding * other.at_price(lp_pool.price).lp_token_price();
+        let converted = holding * lp_pool.lp_conversion_rate(&other);
+        assert!((converted * lp_pool.lp_token_price() - value).abs() < 1e-9);
+        assert!(lp_pool.lp_conversion_rate(&other) < 1.0); // Revaluing `other` at 1.5 loses value
+    }
+
+    #[test]
+    fn test_fee_waiver() {
+        // Tests that swaps before the waiver deadline pay no fee and later ones pay the regular fee.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.set_fee_waiver_until(Some(1000));
+        let mut regular_pool = lp_pool.clone();
+
+        let waived = lp_pool.swap_at(StakedTokenAmount(6000000), 999).unwrap();
+        assert_eq!(waived, 9.0);
+        assert_eq!(lp_pool.stats().fees_collected, TokenAmount(0));
+
+        let regular = regular_pool
+            .swap_at(StakedTokenAmount(6000000), 1000)
+            .unwrap();
+        assert!((regular - 8.19).abs() < 1e-9);
+        assert!((waived - regular - 0.81).abs() < 1e-9);
+        let untimed = lp_pool.swap(StakedTokenAmount(1000000)).unwrap();
+        assert!(untimed < 1.5); // Swaps without a timestamp are never waived
+    }
+
+    #[test]
+    fn test_total_liabilities() {
+        // Tests that, with no protocol fee accrued, the liabilities equal the total value.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.total_liabilities(), TokenAmount(0));
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert_eq!(lp_pool.total_liabilities(), TokenAmount(100810000));
+        assert!(
+            (lp_pool.total_liabilities().0 as f64 / SCALE as f64 - lp_pool.total_value()).abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn test_last_op_pnl() {
+        // Tests that a swap's PnL is the fee it leaves in the pool and liquidity changes are neutral.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.last_op_pnl(), 0.0);
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.last_op_pnl(), 0.0);
+
+        let value_before = lp_pool.total_value();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert!(lp_pool.last_op_pnl() > 0.0);
+        assert!((lp_pool.last_op_pnl() - 0.81).abs() < 1e-9);
+        assert!((lp_pool.total_value() - value_before - lp_pool.last_op_pnl()).abs() < 1e-9);
+
+        lp_pool.remove_liquidity(LpTokenAmount(30000000)).unwrap();
+        assert!(lp_pool.last_op_pnl() >= 0.0); // Rounding favors the remaining LPs
+        assert!(lp_pool.last_op_pnl() < 1e-5);
+    }
+
+    #[test]
+    fn test_equivalent_constant_product() {
+        // Tests that swaps pay out less than a constant-product pool would, so `k` grows.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.equivalent_price(), 0.0);
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.equivalent_k(), 0.0);
+        assert_eq!(lp_pool.equivalent_price(), f64::INFINITY);
+
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let k = lp_pool.equivalent_k();
+        assert!((k - 91.81 * 6.0).abs() < 1e-9);
+        assert!((lp_pool.equivalent_price() - 91.81 / 6.0).abs() < 1e-12);
+
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert!(lp_pool.equivalent_k() > k);
+    }
+
+    #[test]
+    fn test_validate_fee_monotonic() {
+        // Tests that the linear curve passes the check and a fee rising with the balance fails it.
+        #[derive(Debug)]
+        struct RisingFee;
+
+        impl FeeStrategy for RisingFee {
+            fn fee(
+                &self,
+                balance: TokenAmount,
+                target: TokenAmount,
+                min_fee: Percentage,
+                max_fee: Percentage,
+            ) -> Percentage {
+                LinearFeeStrategy.fee(
+                    TokenAmount(target.0.saturating_sub(balance.0)),
+                    target,
+                    min_fee,
+                    max_fee,
+                )
+            }
+        }
+
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.validate_fee_monotonic(100), Ok(()));
+
+        lp_pool.set_fee_strategy(Arc::new(RisingFee));
+        assert_eq!(
+            lp_pool.validate_fee_monotonic(100),
+            Err(Error::InvariantViolation)
+        );
+        assert_eq!(lp_pool.validate_fee_monotonic(1), Ok(()));
+    }
+
+    #[test]
+    fn test_staked_cost_basis() {
+        // Tests that the cost basis is the tokens paid per staked token across swaps at different prices.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.staked_cost_basis(), 0.0);
+        lp_pool.add_liquidity(TokenAmount(200000000)).unwrap();
+
+        let first_paid = lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert!((lp_pool.staked_cost_basis() - 8.19 / 6.0).abs() < 1e-12);
+        lp_pool.update_price(Price(2000000), 0).unwrap();
+        let second_paid = lp_pool.swap(StakedTokenAmount(2000000)).unwrap();
+        assert!((second_paid - 3.64).abs() < 1e-9);
+        let basis = (first_paid + second_paid) / 8.0;
+        assert!((lp_pool.staked_cost_basis() - basis).abs() < 1e-12);
+
+        // Withdrawing a share of the staked balance leaves the average price unchanged
+        lp_pool.remove_liquidity(LpTokenAmount(50000000)).unwrap();
+        assert!((lp_pool.staked_cost_basis() - basis).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_lp_neutral_fee() {
+        // Tests that a swap charged the LP-neutral fee leaves the LP token price unchanged.
+        let mut lp_pool = LpPool::init(
+            Price(1333333),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+
+        for amount in [StakedTokenAmount(6000000), StakedTokenAmount(1000001)] {
+            let fee = lp_pool.lp_neutral_fee(amount).unwrap();
+            assert!(fee.0 <= 1);
+            let mut neutral_pool = LpPool {
+                min_fee: fee,
+                max_fee: fee,
+                ..lp_pool.clone()
+            };
+            let lp_price_before = neutral_pool.lp_token_price();
+            neutral_pool.swap(amount).unwrap();
+            assert!((neutral_pool.lp_token_price() - lp_price_before).abs() < 1e-8);
+        }
+        assert_eq!(
+            lp_pool.lp_neutral_fee(StakedTokenAmount(0)),
+            Err(Error::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_quote_both() {
+        // Tests that each side of the combined quote matches its single-direction pricing.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(20000000)).unwrap();
+
+        let (tokens_out, staked_out) = lp_pool
+            .quote_both(StakedTokenAmount(6000000), TokenAmount(3000000))
+            .unwrap();
+        assert_eq!(
+            tokens_out,
+            lp_pool.clone().swap(StakedTokenAmount(6000000)).unwrap()
+        );
+        let fee = lp_pool
+            .fee_at_balance(TokenAmount(lp_pool.token_amount.0 + 3000000))
+            .to_f64();
+        assert!((staked_out - 3.0 / (1.5 * (1.0 + fee))).abs() < 1e-12);
+
+        // A tiny purchase pays the marginal buy price
+        let (_, staked_out) = lp_pool
+            .quote_both(StakedTokenAmount(6000000), TokenAmount(1))
+            .unwrap();
+        assert!((1e-6 / staked_out - lp_pool.marginal_prices().1).abs() < 1e-6);
+        assert_eq!(
+            lp_pool.quote_both(StakedTokenAmount(6000000), TokenAmount(100000000)),
+            Err(Error::InsufficientLiquidity)
+        );
+    }
+
+    #[test]
+    fn test_time_to_target() {
+        // Tests that the time to drain to the target is the balance gap over the rate.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+
+        // 10 tokens above the target
+        assert_eq!(lp_pool.time_to_target(0.5), Some(20));
+        assert_eq!(lp_pool.time_to_target(3.0), Some(4)); // Rounded up from 3.33 seconds
+        assert_eq!(lp_pool.time_to_target(0.0), None);
+        assert_eq!(lp_pool.time_to_target(f64::NAN), None);
+
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert_eq!(lp_pool.time_to_target(0.5), Some(4)); // 1.81 tokens above the target
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert_eq!(lp_pool.time_to_target(0.5), None);
+    }
+
+    #[test]
+    fn test_drain_resistance() {
+        // Tests that the fee curve caps the share of the balance a single swap can take out.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.drain_resistance(), 0.0);
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+
+        // Below the target a swap worth `g` pays out `g * (1 - 0.009 * g)`, which peaks at
+        // `g = 1 / 0.018` with a payout of `1 / 0.036` tokens
+        let resistance = lp_pool.drain_resistance();
+        assert!(resistance < 1.0);
+        assert!((resistance - 1.0 / 3.6).abs() < 1e-6);
+        let tokens_received = lp_pool
+            .swap(StakedTokenAmount::from(1.0 / 0.018 / 1.5))
+            .unwrap();
+        assert!((tokens_received / 100.0 - resistance).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_lp_burn_for_ratio() {
+        // Tests that burning the returned LP tokens brings the reserve ratio to the target.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(180000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert!(lp_pool.reserve_ratio() > 1.9);
+
+        let lp_to_burn = lp_pool.lp_burn_for_ratio(1.2).unwrap();
+        lp_pool.remove_liquidity(lp_to_burn).unwrap();
+        assert!((lp_pool.reserve_ratio() - 1.2).abs() < 1e-6);
+        assert_eq!(lp_pool.lp_burn_for_ratio(1.5), Err(Error::InvalidInput));
+    }
+
+    #[test]
+    fn test_fixed_bytes_round_trip() {
+        // Tests that the fixed layout is stable byte for byte and restores the same pool.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+
+        let bytes = lp_pool.to_fixed_bytes().unwrap();
+        assert_eq!(bytes[..8], 1500000u64.to_be_bytes());
+        assert_eq!(bytes[8..16], 91810000u64.to_be_bytes());
+        assert_eq!(bytes[48..], 900000u64.to_be_bytes());
+        let restored = LpPool::from_fixed_bytes(&bytes).unwrap();
+        assert_eq!(restored.to_fixed_bytes(), Ok(bytes));
+        assert_eq!(restored.summary(), lp_pool.summary());
+
+        let mut invalid = bytes;
+        invalid[40..48].copy_from_slice(&2000000u64.to_be_bytes()); // A 200% minimum fee
+        assert!(matches!(
+            LpPool::from_fixed_bytes(&invalid),
+            Err(Error::InvalidInput)
+        ));
+    }
+
+    #[test]
+    fn test_tvl_in() {
+        // Tests that the TVL is the total value converted at the reference rate.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert!((lp_pool.tvl_in(2.5) - 100.81 * 2.5).abs() < 1e-9);
+        assert_eq!(lp_pool.tvl_in(1.0), lp_pool.total_value());
+    }
+
+    #[test]
+    fn test_simulate_shock() {
+        // Tests the number of reverse swaps needed to refill the pool after a large withdrawal.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(200000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(30000000)).unwrap();
+
+        // Burning half the LP tokens leaves 79.525 tokens against the target of 90. Buying 2
+        // staked tokens brings in 3 tokens plus a fee falling from 15.7% to 9.6%, so three
+        // swaps reach 89.66 tokens and the fourth crosses the target.
+        assert_eq!(
+            lp_pool.simulate_shock(LpTokenAmount(100000000), StakedTokenAmount(2000000)),
+            Ok(4)
+        );
+        assert_eq!(
+            lp_pool.simulate_shock(LpTokenAmount(10000000), StakedTokenAmount(2000000)),
+            Ok(0)
+        );
+        assert_eq!(
+            lp_pool.simulate_shock(LpTokenAmount(190000000), StakedTokenAmount(2000000)),
+            Err(Error::InsufficientLiquidity)
+        );
+        // Single-unit purchases would need millions of swaps to cover the 10.475 token shortfall
+        assert_eq!(
+            lp_pool.simulate_shock(LpTokenAmount(100000000), StakedTokenAmount(1)),
+            Err(Error::SimulationLimitExceeded)
+        );
+        assert_eq!(lp_pool.token_amount, TokenAmount(159050000));
+    }
+
+    #[test]
+    fn test_fees_per_lp_since() {
+        // Tests that fees accrued since the reference are spread over the LP supply.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.fees_per_lp_since(0.0), 0.0);
+        lp_pool.add_liquidity(TokenAmount(200000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let reference = lp_pool.stats().fees_collected.0 as f64 / SCALE as f64;
+        assert!((reference - 0.81).abs() < 1e-9);
+
+        // Two more swaps at the minimum fee add 2 * 0.81 tokens over 200 LP tokens
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        assert!((lp_pool.fees_per_lp_since(reference) - 1.62 / 200.0).abs() < 1e-12);
+        assert!((lp_pool.fees_per_lp_since(0.0) - 2.43 / 200.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_optimal_deposit_split() {
+        // Tests that the deposit split follows the pool's composition by value.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(
+            lp_pool.optimal_deposit_split(TokenAmount(10000000)),
+            (TokenAmount(10000000), StakedTokenAmount(0))
+        );
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+
+        // 91.81 tokens and 9 tokens of staked value out of 100.81
+        let (tokens, staked) = lp_pool.optimal_deposit_split(TokenAmount(10081000));
+        assert_eq!(tokens, TokenAmount(9181000));
+        assert_eq!(staked, StakedTokenAmount(600000));
+        let staked_value = lp_pool.value_in_tokens(staked).0;
+        let composition = tokens.0 * SCALE as Balance / (tokens.0 + staked_value);
+        assert!(composition.abs_diff(lp_pool.composition().0 as Balance) <= 1);
+    }
+
+    #[test]
+    fn test_lp_loss_from_fee_change() {
+        // Tests that raising the maximum fee never costs LPs value under stress volume.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+
+        // 30 staked tokens push the balance far below the target, into the elevated fees
+        let stress_volume = StakedTokenAmount(30000000);
+        let higher = lp_pool
+            .lp_loss_from_fee_change(Percentage(950000), stress_volume)
+            .unwrap();
+        assert!(higher > 0.0);
+        let lower = lp_pool
+            .lp_loss_from_fee_change(Percentage(500000), stress_volume)
+            .unwrap();
+        assert!(lower < 0.0);
+        assert_eq!(
+            lp_pool.lp_loss_from_fee_change(Percentage(900000), stress_volume),
+            Ok(0.0)
+        );
+        // A small swap stays above the target and only pays the unchanged minimum fee
+        assert_eq!(
+            lp_pool.lp_loss_from_fee_change(Percentage(950000), StakedTokenAmount(1000000)),
+            Ok(0.0)
+        );
+    }
+
+    #[test]
+    fn test_no_arb_band() {
+        // Tests that the no-arbitrage band spans twice the marginal fee around the spot price.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(20000000)).unwrap();
+
+        let (lower, upper) = lp_pool.no_arb_band();
+        let marginal_fee = lp_pool.marginal_fee().to_f64();
+        assert!(lower < 1.5 && upper > 1.5);
+        assert!((upper - lower - 2.0 * 1.5 * marginal_fee).abs() < 1e-12);
+        assert!(((upper + lower) / 2.0 - 1.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_repair_lp_supply() {
+        // Tests that a corrupted LP supply is restored from the backing of the last operation.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        assert_eq!(lp_pool.repair_lp_supply(), Ok(()));
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        lp_pool.swap(StakedTokenAmount(6000000)).unwrap();
+        let lp_token_price = lp_pool.lp_token_price();
+
+        lp_pool.lp_token_amount = LpTokenAmount(1); // Corrupted by a manual edit
+        lp_pool.repair_lp_supply().unwrap();
+        assert_eq!(lp_pool.lp_token_amount, LpTokenAmount(100000000));
+
+        // A migration scaling both balances scales the supply with them
+        lp_pool.token_amount.0 *= 1000;
+        lp_pool.st_token_amount.0 *= 1000;
+        lp_pool.repair_lp_supply().unwrap();
+        assert_eq!(lp_pool.lp_token_amount, LpTokenAmount(100000000000));
+        assert!((lp_pool.lp_token_price() - lp_token_price).abs() < 1e-12);
+
+        // Balances that moved apart leave no consistent supply to restore
+        lp_pool.token_amount.0 /= 2;
+        lp_pool.lp_token_amount = LpTokenAmount(1);
+        assert_eq!(lp_pool.repair_lp_supply(), Err(Error::InvariantViolation));
+        assert_eq!(lp_pool.lp_token_amount, LpTokenAmount(1));
+    }
+
+    #[test]
+    fn test_fee_elasticity() {
+        // Tests that fee revenue rises with the maximum fee only once swaps dip below the target.
+        let mut lp_pool = LpPool::init(
+            Price(1500000),
+            Percentage(90000),
+            Percentage(900000),
+            TokenAmount(90000000),
+        )
+        .unwrap();
+        lp_pool.add_liquidity(TokenAmount(80000000)).unwrap();
+
+        // Swapping 6 staked tokens leaves 71 tokens of the 90 target: the fee is
+        // `max - (max - 0.09) * 71 / 90`, so 19 / 90 of each change in `max_fee` on 9 tokens
+        let elasticity = lp_pool.fee_elasticity(StakedTokenAmount(6000000)).unwrap();
+        assert!(elasticity > 0.0);
+        assert!((elasticity - 9.0 * 19.0 / 90.0).abs() < 1e-2); // Payouts round to whole scaled units
+
+        lp_pool.add_liquidity(TokenAmount(100000000)).unwrap();
+        assert_eq!(lp_pool.fee_elasticity(StakedTokenAmount(6000000)), Ok(0.0));
+        assert_eq!(
+            lp_pool.fee_elasticity(StakedTokenAmount(0)),
+            Err(Error::InvalidInput)
+        );
+    }
+
+    // Tests that public methods fail cleanly instead of panicking on balances and inputs at the
+    // edge of the `Balance` range.
+    mod overflow {
+        use super::*;
+
+        // Build a pool holding the given raw balances.
+        fn from_raw(
+            price: u64,
+            token_amount: Balance,
+            st_token_amount: Balance,
+            lp_token_amount: Balance,
+        ) -> LpPool {
+            let mut lp_pool = LpPool::init(
+                Price(price),
+                Percentage(90000),
+                Percentage(900000),
+                TokenAmount(90000000),
+            )
+            .unwrap();
+            lp_pool.token_amount = TokenAmount(token_amount);
+            lp_pool.st_token_amount = StakedTokenAmount(st_token_amount);
+            lp_pool.lp_token_amount = LpTokenAmount(lp_token_amount);
+            lp_pool
+        }
+
+        #[test]
+        fn test_add_liquidity_at_extremes() {
+            // Tests that deposits pushing the token or LP supply past the maximum fail with Overflow.
+            let mut lp_pool = from_raw(1500000, Balance::MAX - 1, 0, Balance::MAX - 1);
+            assert_eq!(
+                lp_pool.add_liquidity(TokenAmount(Balance::MAX)),
+                Err(Error::Overflow)
+            );
+            assert_eq!(lp_pool.add_liquidity(TokenAmount(2)), Err(Error::Overflow));
+            assert_eq!(
+                lp_pool.add_liquidity_batch(&[TokenAmount(1), TokenAmount(Balance::MAX)]),
+                Err(Error::Overflow)
+            );
+            assert_eq!(lp_pool.token_amount, TokenAmount(Balance::MAX - 1));
+
+            let mut empty_pool = from_raw(1500000, 0, 0, 0);
+            assert_eq!(
+                empty_pool.add_liquidity(TokenAmount(Balance::MAX)),
+                Ok(Balance::MAX as f64 / SCALE as f64)
+            );
+            // Doubling the pool halves every share, unless the doubled value exceeds a u128
+            let expected_dilution = if cfg!(feature = "u128") {
+                Err(Error::Overflow)
+            } else {
+                Ok(Percentage(500000))
+            };
+            assert_eq!(
+                empty_pool.dilution(TokenAmount(Balance::MAX)),
+                expected_dilution
+            );
+        }
+
+        #[test]
+        fn test_remove_liquidity_at_extremes() {
+            // Tests that withdrawals from a pool holding maximum balances neither overflow nor overpay.
+            let mut lp_pool = from_raw(u64::MAX, Balance::MAX, Balance::MAX, Balance::MAX);
+            assert_eq!(
+                lp_pool.remove_liquidity(LpTokenAmount(Balance::MAX - 1)),
+                Ok((
+                    (Balance::MAX - 1) as f64 / SCALE as f64,
+                    (Balance::MAX - 1) as f64 / SCALE as f64
+                ))
+            );
+            assert_eq!(
+                lp_pool.remove_liquidity(LpTokenAmount(1)),
+                Ok((1.0 / SCALE as f64, 1.0 / SCALE as f64))
+            );
+            assert_eq!(
+                lp_pool.remove_liquidity(LpTokenAmount(1)),
+                Err(Error::InsufficientLiquidity)
+            );
+            assert_eq!(
+                lp_pool.lp_for_token_out(TokenAmount(Balance::MAX)),
+                Err(Error::InsufficientLiquidity)
+            );
+        }
+
+        #[test]
+        fn test_swap_at_extremes() {
+            // Tests that swaps too large for the pool's balances fail with a clean error.
+            let mut lp_pool = from_raw(u64::MAX, 100000000, 0, 100000000);
+            assert_eq!(
+                lp_pool.swap(StakedTokenAmount(Balance::MAX)),
+                Err(Error::InsufficientLiquidity)
+            );
+            assert_eq!(
+                lp_pool.quote_exact_out(TokenAmount(Balance::MAX)),
+                Err(Error::InsufficientLiquidity)
+            );
+            assert_eq!(
+                lp_pool.fee_amount(StakedTokenAmount(Balance::MAX)),
+                Err(Error::InsufficientLiquidity)
+            );
+            // Even a single staked unit is worth more than the pool holds
+            assert_eq!(
+                lp_pool.swap_clamped(StakedTokenAmount(Balance::MAX)),
+                Err(Error::InsufficientLiquidity)
+            );
+
+            let mut full_pool = from_raw(1, Balance::MAX, Balance::MAX, Balance::MAX);
+            assert_eq!(
+                full_pool.swap(StakedTokenAmount(SCALE as Balance)),
+                Err(Error::Overflow)
+            );
+            assert_eq!(full_pool.st_token_amount, StakedTokenAmount(Balance::MAX));
+        }
+
+        #[test]
+        fn test_price_update_at_extremes() {
+            // Tests that the extreme prices are handled and valuations saturate instead of wrapping.
+            let mut lp_pool = from_raw(1500000, Balance::MAX, Balance::MAX, Balance::MAX);
+            assert_eq!(lp_pool.update_price(Price(0), 0), Err(Error::InvalidInput));
+            assert_eq!(lp_pool.update_price(Price(u64::MAX), 0), Ok(()));
+            assert_eq!(
+                lp_pool.value_in_tokens(StakedTokenAmount(Balance::MAX)),
+                TokenAmount(Balance::MAX)
+            );
+            assert!(lp_pool.total_value().is_finite());
+            assert!(lp_pool.lp_token_price().is_finite());
+            // The staked balance is already at the maximum, so not even one more unit fits
+            assert_eq!(
+                lp_pool.price_to_preserve_lp_value(StakedTokenAmount(1)),
+                Err(Error::Overflow)
+            );
+            // The deposit is a third of the pool's value after it, unless that exceeds a u128
+            let expected_dilution = if cfg!(feature = "u128") {
+                Err(Error::Overflow)
+            } else {
+                Ok(Percentage(333333))
+            };
+            assert_eq!(
+                lp_pool.dilution(TokenAmount(Balance::MAX)),
+                expected_dilution
+            );
+            assert_eq!(lp_pool.leverage(), 1.0);
+            // The staked balance was never bought, so its whole saturated value is unrealized
+            assert_eq!(
+                lp_pool.fee_split(),
+                (0.0, Balance::MAX as f64 / SCALE as f64)
+            );
+            assert_eq!(
+                lp_pool.round_trip_cost(TokenAmount(Balance::MAX)),
+                Err(Error::Overflow)
+            );
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt;
+
+use crate::error::Error;
+
+// Scaled integer type backing token, staked token and LP token balances.
+// The `u128` feature widens it for pools whose balances exceed the `u64` range.
+#[cfg(not(feature = "u128"))]
+pub type Balance = u64;
+#[cfg(feature = "u128")]
+pub type Balance = u128;
+
+// Data structure definitions representing various values in the liquidity pool.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenAmount(pub Balance);
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StakedTokenAmount(pub Balance);
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LpTokenAmount(pub Balance);
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Price(pub u64);
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Percentage(pub u64);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(pub u64);
+
+pub const SCALE: u64 = 1_000_000;
+
+pub(crate) const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+// Scale an f64 value to an integer, reporting values too large to be represented instead of saturating.
+// NaN, infinite and negative values are rejected as `Error::InvalidInput`.
+//
+// The checked conversions are inherent `try_from_f64` methods because the `From<f64>` impls
+// already give every type a blanket, infallible `TryFrom<f64>`.
+fn scale_f64_checked<T: TryFrom<u128>>(value: f64) -> Result<T, Error> {
+    if !value.is_finite() || value < 0.0 {
+        return Err(Error::InvalidInput);
+    }
+    let scaled = value * SCALE as f64;
+    if scaled >= u128::MAX as f64 {
+        return Err(Error::Overflow);
+    }
+    T::try_from(scaled as u128).map_err(|_| Error::Overflow)
+}
+
+// Format a scaled value as a decimal with `places` decimal places, truncating any further
+// digits. `Display` shows every decimal `SCALE` holds.
+fn format_scaled(scaled: u128, places: usize) -> String {
+    let scale_places = SCALE.ilog10() as usize;
+    let whole = scaled / SCALE as u128;
+    if places == 0 {
+        return whole.to_string();
+    }
+
+    let fraction = format!("{:0scale_places$}", scaled % SCALE as u128);
+    let fraction: String = fraction.chars().take(places).collect();
+    format!("{whole}.{fraction:0<places$}")
+}
+
+// Checking if the input is u64 or f64, and making conversion accordingly.
+// The `From<f64>` conversions cannot fail: they truncate toward zero, map NaN and negative
+// values to 0 and saturate values that are too large. Use `try_from_f64` for untrusted input.
+impl From<f64> for Price {
+    fn from(value: f64) -> Self {
+        Price((value * SCALE as f64) as u64)
+    }
+}
+
+impl Price {
+    pub fn try_from_f64(value: f64) -> Result<Self, Error> {
+        scale_f64_checked(value).map(Price)
+    }
+
+    pub fn to_string_with_precision(&self, places: usize) -> String {
+        format_scaled(self.0 as u128, places)
+    }
+}
+
+impl Percentage {
+    pub fn try_from_f64(value: f64) -> Result<Self, Error> {
+        scale_f64_checked(value).map(Percentage)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / SCALE as f64
+    }
+
+    pub fn to_string_with_precision(&self, places: usize) -> String {
+        format_scaled(self.0 as u128, places)
+    }
+
+    // Annualize a rate charged once every `period_secs` seconds, without compounding.
+    pub fn to_annualized(self, period_secs: u64) -> f64 {
+        if period_secs == 0 {
+            return 0.0;
+        }
+
+        self.to_f64() * (SECONDS_PER_YEAR as f64 / period_secs as f64)
+    }
+}
+
+impl From<f64> for TokenAmount {
+    fn from(value: f64) -> Self {
+        TokenAmount((value * SCALE as f64) as Balance)
+    }
+}
+
+impl TokenAmount {
+    pub fn try_from_f64(value: f64) -> Result<Self, Error> {
+        scale_f64_checked(value).map(TokenAmount)
+    }
+
+    pub fn to_string_with_precision(&self, places: usize) -> String {
+        format_scaled(self.0 as u128, places)
+    }
+}
+
+impl From<f64> for StakedTokenAmount {
+    fn from(value: f64) -> Self {
+        StakedTokenAmount((value * SCALE as f64) as Balance)
+    }
+}
+
+impl StakedTokenAmount {
+    pub fn try_from_f64(value: f64) -> Result<Self, Error> {
+        scale_f64_checked(value).map(StakedTokenAmount)
+    }
+
+    pub fn to_string_with_precision(&self, places: usize) -> String {
+        format_scaled(self.0 as u128, places)
+    }
+}
+
+impl From<f64> for LpTokenAmount {
+    fn from(value: f64) -> Self {
+        LpTokenAmount((value * SCALE as f64) as Balance)
+    }
+}
+
+impl LpTokenAmount {
+    pub fn try_from_f64(value: f64) -> Result<Self, Error> {
+        scale_f64_checked(value).map(LpTokenAmount)
+    }
+
+    pub fn to_string_with_precision(&self, places: usize) -> String {
+        format_scaled(self.0 as u128, places)
+    }
+}
+
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_scaled(self.0 as u128, SCALE.ilog10() as usize))
+    }
+}
+
+impl fmt::Display for StakedTokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_scaled(self.0 as u128, SCALE.ilog10() as usize))
+    }
+}
+
+impl fmt::Display for LpTokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_scaled(self.0 as u128, SCALE.ilog10() as usize))
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_scaled(self.0 as u128, SCALE.ilog10() as usize))
+    }
+}
+
+impl fmt::Display for Percentage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_scaled(self.0 as u128, SCALE.ilog10() as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_from_f64_overflow() {
+        // Tests that converting a value too large for the scaled u64 representation fails instead of saturating.
+        #[cfg(not(feature = "u128"))]
+        {
+            assert_eq!(
+                TokenAmount::try_from_f64(1e20).unwrap_err(),
+                Error::Overflow
+            );
+            assert_eq!(
+                StakedTokenAmount::try_from_f64(1e20).unwrap_err(),
+                Error::Overflow
+            );
+            assert_eq!(
+                LpTokenAmount::try_from_f64(1e20).unwrap_err(),
+                Error::Overflow
+            );
+        }
+        assert_eq!(Price::try_from_f64(1e20).unwrap_err(), Error::Overflow);
+        assert_eq!(TokenAmount::try_from_f64(100.0).unwrap().0, 100000000);
+    }
+
+    #[test]
+    fn test_percentage_to_annualized() {
+        // Tests that a 0.1% fee charged every day annualizes to 36.5%.
+        let daily_fee = Percentage(1000);
+        assert!((daily_fee.to_annualized(24 * 60 * 60) - 0.365).abs() < 1e-12);
+        assert!((daily_fee.to_annualized(SECONDS_PER_YEAR) - 0.001).abs() < 1e-12);
+        assert_eq!(daily_fee.to_annualized(0), 0.0);
+    }
+
+    #[test]
+    fn test_try_from_f64_rejects_invalid_values() {
+        // Tests that every checked conversion accepts valid values and rejects NaN, infinite and negative ones.
+        assert_eq!(TokenAmount::try_from_f64(1.5), Ok(TokenAmount(1500000)));
+        assert_eq!(
+            StakedTokenAmount::try_from_f64(1.5),
+            Ok(StakedTokenAmount(1500000))
+        );
+        assert_eq!(LpTokenAmount::try_from_f64(1.5), Ok(LpTokenAmount(1500000)));
+        assert_eq!(Price::try_from_f64(1.5), Ok(Price(1500000)));
+        assert_eq!(Percentage::try_from_f64(0.09), Ok(Percentage(90000)));
+
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -1.0] {
+            assert_eq!(TokenAmount::try_from_f64(value), Err(Error::InvalidInput));
+            assert_eq!(
+                StakedTokenAmount::try_from_f64(value),
+                Err(Error::InvalidInput)
+            );
+            assert_eq!(LpTokenAmount::try_from_f64(value), Err(Error::InvalidInput));
+            assert_eq!(Price::try_from_f64(value), Err(Error::InvalidInput));
+            assert_eq!(Percentage::try_from_f64(value), Err(Error::InvalidInput));
+        }
+        assert_eq!(Percentage::try_from_f64(1e20), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn test_to_string_with_precision() {
+        // Tests that scaled values format to the requested decimals, truncating the rest.
+        let tokens = TokenAmount(100123456);
+        assert_eq!(tokens.to_string_with_precision(2), "100.12");
+        assert_eq!(tokens.to_string_with_precision(0), "100");
+        assert_eq!(tokens.to_string_with_precision(8), "100.12345600");
+        assert_eq!(tokens.to_string(), "100.123456");
+        assert_eq!(Percentage(90000).to_string_with_precision(3), "0.090");
+        assert_eq!(StakedTokenAmount(1500000).to_string(), "1.500000");
+        assert_eq!(LpTokenAmount(5).to_string_with_precision(6), "0.000005");
+        assert_eq!(Price(1500000).to_string_with_precision(1), "1.5");
+    }
+}
@@ -0,0 +1,22 @@
+use liquiditypool::{
+    Error, LpPool, LpTokenAmount, Percentage, Price, StakedTokenAmount, TokenAmount,
+};
+
+#[test]
+fn test_pool_from_crate_root() {
+    // Tests that the public API can be used through the crate root re-exports.
+    let mut lp_pool = LpPool::init(
+        Price::from(1.5),
+        Percentage(1000),
+        Percentage(90000),
+        TokenAmount::from(90.0),
+    )
+    .unwrap();
+    assert_eq!(lp_pool.add_liquidity(TokenAmount::from(100.0)), Ok(100.0));
+    assert!(lp_pool.swap(StakedTokenAmount::from(6.0)).is_ok());
+    assert!(lp_pool.remove_liquidity(LpTokenAmount::from(100.0)).is_ok());
+    assert_eq!(
+        lp_pool.swap(StakedTokenAmount::from(6.0)),
+        Err(Error::InsufficientLiquidity)
+    );
+}